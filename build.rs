@@ -15,6 +15,8 @@ fn main() {
     println!("cargo:rerun-if-changed=memory.x");
 
     generate_sin_table();
+    generate_atten_table();
+    generate_g3ruh_pulse();
 }
 
 fn generate_sin_table() {
@@ -25,16 +27,72 @@ fn generate_sin_table() {
     let dest_path = Path::new(&out_dir).join("sine_table.rs");
     let mut file = File::create(dest_path).unwrap();
 
-    writeln!(file, "static SINE_TABLE: [u32; {}] = [", TABLE_SIZE).unwrap();
+    writeln!(file, "static SINE_TABLE: [i16; {}] = [", TABLE_SIZE).unwrap();
 
     for i in 0..TABLE_SIZE {
         let phase = (i as f32 / TABLE_SIZE as f32) * 2.0 * PI;
         let sample_i16 = (phase.sin() * AMPLITUDE).round() as i16;
-        let sample_u16 = sample_i16 as u16;
-        // duplicate into both halves
-        let word = ((sample_u16 as u32) << 16) | sample_u16 as u32;
-        writeln!(file, "    0x{:08X}, // Amplitude: {}", word, sample_i16).unwrap();
+        writeln!(file, "    {},", sample_i16).unwrap();
     }
 
     writeln!(file, "];").unwrap();
-}
\ No newline at end of file
+}
+
+// Q15 gain for 0, 1, 2... dB of attenuation, for twist control
+fn generate_atten_table() {
+    const MAX_DB: usize = 12;
+
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let dest_path = Path::new(&out_dir).join("atten_table.rs");
+    let mut file = File::create(dest_path).unwrap();
+
+    writeln!(file, "static ATTEN_DB_Q15: [u16; {}] = [", MAX_DB + 1).unwrap();
+
+    for db in 0..=MAX_DB {
+        let gain = 10f32.powf(-(db as f32) / 20.0);
+        let q15 = (gain * 32768.0).round() as u16;
+        writeln!(file, "    {}, // -{} dB", q15, db).unwrap();
+    }
+
+    writeln!(file, "];").unwrap();
+}
+
+// Raised cosine pulse for the G3RUH modulator, sampled at PHASES points per
+// bit over SPAN bits and scaled so no sum of SPAN taps can clip
+fn generate_g3ruh_pulse() {
+    const SPAN: usize = 4;
+    const PHASES: usize = 16;
+    const ROLLOFF: f32 = 0.5;
+
+    let pulse = |t: f32| {
+        let sinc = if t == 0.0 { 1.0 } else { (PI * t).sin() / (PI * t) };
+        let d = 1.0 - (2.0 * ROLLOFF * t).powi(2);
+        if d.abs() < 1e-6 {
+            PI / 4.0 * sinc
+        } else {
+            sinc * (PI * ROLLOFF * t).cos() / d
+        }
+    };
+
+    let taps: Vec<f32> = (0..SPAN * PHASES)
+        .map(|i| pulse(i as f32 / PHASES as f32 - (SPAN / 2) as f32))
+        .collect();
+    let worst = (0..PHASES)
+        .map(|ph| (0..SPAN).map(|k| taps[k * PHASES + ph].abs()).sum::<f32>())
+        .fold(0.0, f32::max);
+    let scale = i16::MAX as f32 / worst;
+
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let dest_path = Path::new(&out_dir).join("g3ruh_pulse.rs");
+    let mut file = File::create(dest_path).unwrap();
+
+    writeln!(file, "const PULSE_SPAN: usize = {};", SPAN).unwrap();
+    writeln!(file, "const PULSE_PHASES: usize = {};", PHASES).unwrap();
+    writeln!(file, "static G3RUH_PULSE: [i16; {}] = [", SPAN * PHASES).unwrap();
+
+    for t in taps {
+        writeln!(file, "    {},", (t * scale).round() as i16).unwrap();
+    }
+
+    writeln!(file, "];").unwrap();
+}
@@ -4,8 +4,11 @@ MEMORY {
      * Here we assume you have 2048 KiB of Flash. This is what the Pi Pico
      * has, but your board may have more or less Flash and you should adjust
      * this value to suit.
+     *
+     * The last 4 KiB sector is left out of the image: `hardware::flash`
+     * keeps the last known position there.
      */
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 4K
     /*
      * RAM consists of 4 banks, SRAM0-SRAM3, with a striped mapping.
      * This is usually good for performance, as it distributes load on
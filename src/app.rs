@@ -1,5 +1,3 @@
-use core::str::FromStr;
-
 use nmea::{Nmea, SentenceType};
 use rp_pico as bsp;
 use bsp::hal;
@@ -8,18 +6,47 @@ use hal::pac;
 use pac::{CorePeripherals, Peripherals};
 
 use crate::aprs::{Coordinate, PositionReport};
-use crate::ax25::TxBits;
 use crate::beacon::BeaconTask;
+use crate::config::Config;
 use crate::display::DisplayTask;
 use crate::gps::GpsTask;
+use crate::gpstime::GpsClock;
+use crate::lastfix::LastKnown;
 use crate::hardware::Hardware;
+use crate::hardware::{audio, flash};
+use crate::input::{Events, InputTask};
+#[cfg(feature = "g3ruh")]
+use crate::g3ruh::G3ruhModulator;
+#[cfg(not(feature = "g3ruh"))]
 use crate::modem::AfskModulator;
+use crate::modem::{Calibration, TxTask};
+use crate::onair::TxFrame;
+use crate::rng::Rng;
+use crate::rx::RxTask;
 use crate::sched::{Scheduler, Tickable};
+use crate::co::TX_QUEUE_LEN;
 
 pub struct Shared {
+    pub config: Config,
+    /// A packet signal is on the channel (data carrier detect).
+    pub dcd: bool,
+    /// Test tone for the modem to send, for setting deviation.
+    pub calibrate: Option<Calibration>,
     pub nmea: Nmea,
+    /// UTC from the GPS, against uptime.
+    pub clock: GpsClock,
+    /// Whether the GPS has a 3D fix, from the last GSA sentence.
+    pub fix_3d: Option<bool>,
+    /// Last good fix, restored from flash at start-up.
+    pub last_fix: LastKnown,
+    /// Symbol and options for our position reports.
     pub pos_rpt: PositionReport,
-    pub txq: heapless::Deque<TxBits, 2>,
+    pub txq: heapless::Deque<TxFrame, TX_QUEUE_LEN>,
+    /// For channel access and beacon timing, seeded from hardware noise at
+    /// start-up.
+    pub rng: Rng,
+    /// From the button, for whichever task wants them.
+    pub events: Events,
 }
 
 impl Shared {
@@ -39,35 +66,54 @@ impl Shared {
             longitude: Coordinate { microdegrees: 0 },
             symbol_table: '/',
             symbol_code: 'n',
-            comment: Some(heapless::String::<43>::from_str("github.com/anthonydotmoe/pico-aprs-beacon").unwrap()),
+            // Each beacon profile has its own
+            comment: None,
             timestamp: None,
             messaging: false,
+            ambiguity: 0,
         };
 
         Self {
+            config: Config::default(),
+            dcd: false,
+            calibrate: None,
             nmea,
+            clock: GpsClock::new(),
+            fix_3d: None,
+            last_fix: LastKnown::new(),
             pos_rpt,
             txq: heapless::Deque::new(),
+            rng: Rng::new(0),
+            events: Events::new(),
         }
     }
 }
 
 pub fn run(pac: Peripherals, core: CorePeripherals) -> ! {
-    let hw = Hardware::init(pac, core);
+    let mut shared = Shared::new();
+
+    let hw = Hardware::init(pac, core, &shared.config.ptt);
+    shared.last_fix = LastKnown::restore(flash::read());
+    shared.rng = Rng::new(hw.seed);
     let mut display_task = DisplayTask::new(hw.display);
+    let mut input_task = InputTask::new(hw.button);
     let mut gps_task = GpsTask::new();
     let mut beacon_task = BeaconTask::new();
-    let mut modem_task = AfskModulator::new();
+    #[cfg(not(feature = "g3ruh"))]
+    let mut modem_task = TxTask::new(AfskModulator::new(audio::sample_rate()));
+    #[cfg(feature = "g3ruh")]
+    let mut modem_task = TxTask::new(G3ruhModulator::new(audio::sample_rate()));
+    let mut rx_task = RxTask::new();
 
-    let mut task_list: [&mut dyn Tickable; 4] = [
+    let mut task_list: [&mut dyn Tickable; 6] = [
         &mut display_task,
+        &mut input_task,
         &mut gps_task,
         &mut beacon_task,
         &mut modem_task,
+        &mut rx_task,
     ];
 
-    let mut shared = Shared::new();
-    
     let mut scheduler = Scheduler::new(&mut task_list, &mut shared);
 
     loop {
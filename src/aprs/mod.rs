@@ -8,7 +8,7 @@ pub enum AprsInfo {
     Unknown(u8, heapless::Vec<u8, 256>),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Timestamp {
     Dhm { day: u8, hour: u8, minute: u8 },
     Hms { hour: u8, minute: u8, second: u8 },
@@ -31,7 +31,10 @@ impl Timestamp {
     }
 }
 
-#[derive(Debug)]
+/// Microdegrees of latitude per kilometre, near enough.
+pub const UDEG_PER_KM: i64 = 9_009;
+
+#[derive(Clone, Debug)]
 pub struct Coordinate {
     pub microdegrees: i32,
 }
@@ -65,9 +68,30 @@ impl Coordinate {
         };
         out.write_char(suffix)
     }
+
+    /// As `to_aprs`, with the last `ambiguity` digits (up to four: hundredths,
+    /// then minutes) left blank to hide exactly where we are.
+    pub fn to_aprs_ambiguous<W: Write>(&self, lat: bool, ambiguity: u8, out: &mut W) -> Result<(), core::fmt::Error> {
+        let mut text = heapless::String::<10>::new();
+        self.to_aprs(lat, &mut text)?;
+
+        let mut bytes = text.into_bytes();
+        let mut left = ambiguity.min(4);
+        // From the end, past the hemisphere
+        for b in bytes.iter_mut().rev().skip(1) {
+            if left == 0 {
+                break;
+            }
+            if b.is_ascii_digit() {
+                *b = b' ';
+                left -= 1;
+            }
+        }
+        out.write_str(core::str::from_utf8(&bytes).map_err(|_| core::fmt::Error)?)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct PositionReport {
     pub latitude: Coordinate,
     pub longitude: Coordinate,
@@ -76,6 +100,8 @@ pub struct PositionReport {
     pub comment: Option<heapless::String<43>>,
     pub timestamp: Option<Timestamp>,
     pub messaging: bool,
+    /// Position ambiguity: how many trailing digits to blank, 0 to 4.
+    pub ambiguity: u8,
 }
 
 impl PositionReport {
@@ -95,13 +121,13 @@ impl PositionReport {
         }
 
         // Latitude
-        self.latitude.to_aprs(true, buf).unwrap();
+        self.latitude.to_aprs_ambiguous(true, self.ambiguity, buf).unwrap();
 
         // Sym Table ID
         buf.write_char(self.symbol_table).unwrap();
 
         // Longitude
-        self.longitude.to_aprs(false, buf).unwrap();
+        self.longitude.to_aprs_ambiguous(false, self.ambiguity, buf).unwrap();
 
         // Symbol Code
         buf.write_char(self.symbol_code).unwrap();
@@ -115,7 +141,7 @@ impl PositionReport {
     }
 }
 
-fn split_callsign_ssid(input: &str) -> (&str, u8) {
+pub(crate) fn split_callsign_ssid(input: &str) -> (&str, u8) {
     let (call, ssid) = match input.split_once('-') {
         Some((call, ssid)) => (call.trim(), ssid),
         None => (input.trim(), "0"),
@@ -125,9 +151,134 @@ fn split_callsign_ssid(input: &str) -> (&str, u8) {
     (call, ssid)
 }
 
+/// A digipeater path, as text: calls with SSIDs, comma separated, such as
+/// `WIDE1-1,WIDE2-1`. Empty for direct.
+pub type Path = heapless::String<32>;
+
+/// An object: a position that isn't ours, such as an event or a repeater.
+#[derive(Clone, Debug)]
+pub struct ObjectReport {
+    /// Up to nine characters.
+    pub name: heapless::String<9>,
+    pub timestamp: Timestamp,
+    pub latitude: Coordinate,
+    pub longitude: Coordinate,
+    pub symbol_table: char,
+    pub symbol_code: char,
+    pub comment: Option<heapless::String<43>>,
+}
+
+impl ObjectReport {
+    pub fn encode<W: Write>(&self, buf: &mut W) -> Result<(), core::fmt::Error> {
+        // Name padded to nine, and `*` for a live object
+        write!(buf, ";{:<9}*", self.name.as_str())?;
+        self.timestamp.encode(buf)?;
+        self.latitude.to_aprs(true, buf)?;
+        buf.write_char(self.symbol_table)?;
+        self.longitude.to_aprs(false, buf)?;
+        buf.write_char(self.symbol_code)?;
+        if let Some(s) = &self.comment {
+            buf.write_str(s)?;
+        }
+        Ok(())
+    }
+}
+
+/// A telemetry report: five analog channels of 0 to 255 and eight bits.
+#[derive(Clone, Debug)]
+pub struct Telemetry {
+    /// Sequence number, 0 to 999.
+    pub seq: u16,
+    pub analog: [u8; 5],
+    pub digital: u8,
+}
+
+impl Telemetry {
+    pub fn encode<W: Write>(&self, buf: &mut W) -> Result<(), core::fmt::Error> {
+        write!(buf, "T#{:03}", self.seq % 1000)?;
+        for a in self.analog {
+            write!(buf, ",{:03}", a)?;
+        }
+        write!(buf, ",{:08b}", self.digital)
+    }
+}
+
+/// Path for the `n`th position beacon under proportional pathing. Every
+/// eighth goes two hops, the other even ones one, and the odd ones direct:
+/// nearby stations hear every beacon, ones further off fewer.
+pub fn proportional_path(n: u32) -> &'static str {
+    if n.is_multiple_of(8) {
+        "WIDE1-1,WIDE2-1"
+    } else if n.is_multiple_of(2) {
+        "WIDE1-1"
+    } else {
+        ""
+    }
+}
+
+/// Six-character Maidenhead locator for a position in microdegrees.
+pub fn maidenhead(latitude: i32, longitude: i32) -> heapless::String<6> {
+    // Both from the south-west corner, in units of 1/24 of a subsquare
+    // (2.5' of latitude, 5' of longitude)
+    let lon = (longitude as i64 + 180_000_000).clamp(0, 359_999_999) * 12 / 1_000_000;
+    let lat = (latitude as i64 + 90_000_000).clamp(0, 179_999_999) * 24 / 1_000_000;
+
+    let mut out = heapless::String::new();
+    for c in [
+        b'A' + (lon / 240) as u8,
+        b'A' + (lat / 240) as u8,
+        b'0' + (lon / 24 % 10) as u8,
+        b'0' + (lat / 24 % 10) as u8,
+        b'a' + (lon % 24) as u8,
+        b'a' + (lat % 24) as u8,
+    ] {
+        let _ = out.push(c as char);
+    }
+    out
+}
+
 pub fn build_position_frame(
     report: &PositionReport,
+    path: &str,
+) -> Result<heapless::Vec<u8, {crate::ax25::MAX_FRAME_LEN}>, ()> {
+    let mut info = heapless::String::<{ crate::ax25::MAX_INFO_LEN }>::new();
+    report.encode(&mut info)?;
+
+    build_frame(info.as_bytes(), path)
+}
+
+/// Builds a status report: free text, no position.
+pub fn build_status_frame(
+    text: &str,
+    path: &str,
+) -> Result<heapless::Vec<u8, {crate::ax25::MAX_FRAME_LEN}>, ()> {
+    let mut info = heapless::String::<{ crate::ax25::MAX_INFO_LEN }>::new();
+    write!(info, ">{}", text).map_err(|_| ())?;
+
+    build_frame(info.as_bytes(), path)
+}
+
+pub fn build_object_frame(
+    report: &ObjectReport,
+    path: &str,
 ) -> Result<heapless::Vec<u8, {crate::ax25::MAX_FRAME_LEN}>, ()> {
+    let mut info = heapless::String::<{ crate::ax25::MAX_INFO_LEN }>::new();
+    report.encode(&mut info).map_err(|_| ())?;
+
+    build_frame(info.as_bytes(), path)
+}
+
+pub fn build_telemetry_frame(
+    report: &Telemetry,
+    path: &str,
+) -> Result<heapless::Vec<u8, {crate::ax25::MAX_FRAME_LEN}>, ()> {
+    let mut info = heapless::String::<{ crate::ax25::MAX_INFO_LEN }>::new();
+    report.encode(&mut info).map_err(|_| ())?;
+
+    build_frame(info.as_bytes(), path)
+}
+
+fn build_frame(info: &[u8], path: &str) -> Result<heapless::Vec<u8, {crate::ax25::MAX_FRAME_LEN}>, ()> {
     use crate::ax25::{self, AddressField};
 
     let (dest_call, dest_ssid) = split_callsign_ssid(crate::co::TOCALL);
@@ -135,10 +286,89 @@ pub fn build_position_frame(
 
     let dest = AddressField::from_text(dest_call, dest_ssid)?;
     let src = AddressField::from_text(src_call, src_ssid)?;
-    let digipeaters = [AddressField::from_text("WIDE1", 1)?];
 
-    let mut info = heapless::String::<{ crate::ax25::MAX_INFO_LEN }>::new();
-    report.encode(&mut info)?;
+    let mut digipeaters = heapless::Vec::<AddressField, { ax25::MAX_DIGIPEATERS }>::new();
+    for hop in path.split(',').map(str::trim).filter(|h| !h.is_empty()) {
+        let (call, ssid) = split_callsign_ssid(hop);
+        digipeaters.push(AddressField::from_text(call, ssid)?).map_err(|_| ())?;
+    }
 
-    ax25::build_ui_frame(dest, src, &digipeaters, info.as_bytes())
-}
\ No newline at end of file
+    ax25::build_ui_frame(dest, src, &digipeaters, info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(frame: &[u8]) -> &[u8] {
+        // Addresses end at the byte with bit 0 set; then control and PID
+        let end = frame.iter().position(|b| b & 1 == 1).unwrap() + 1;
+        &frame[end + 2..frame.len() - 2]
+    }
+
+    #[test]
+    fn maidenhead_locators() {
+        assert_eq!(maidenhead(51_477_811, -1_450), "IO91xl");
+        assert_eq!(maidenhead(49_058_333, -72_029_166), "FN39xb");
+        assert_eq!(maidenhead(-33_856_800, 151_215_300), "QF56od");
+        assert_eq!(maidenhead(90_000_000, 180_000_000), "RR99xx");
+    }
+
+    #[test]
+    fn paths_set_the_digipeaters() {
+        let status = |path| build_status_frame("hi", path).unwrap();
+        // Dest and source only, then one and two hops
+        assert_eq!(status("").len(), 14 + 2 + 3 + 2);
+        assert_eq!(status("WIDE1-1").len(), 21 + 2 + 3 + 2);
+        let two = status("WIDE1-1, WIDE2-1");
+        assert_eq!(two.len(), 28 + 2 + 3 + 2);
+        // Last hop's SSID byte: SSID 1, end of address
+        assert_eq!(two[27], 0x60 | (1 << 1) | 1);
+        assert_eq!(info(&two), b">hi");
+        assert!(build_status_frame("hi", "A,B,C,D,E,F,G,H,I").is_err());
+    }
+
+    #[test]
+    fn ambiguity_blanks_trailing_digits() {
+        let coord = |lat, microdegrees, ambiguity| {
+            let mut out = heapless::String::<10>::new();
+            Coordinate { microdegrees }.to_aprs_ambiguous(lat, ambiguity, &mut out).unwrap();
+            out
+        };
+        assert_eq!(coord(true, 49_058_334, 0), "4903.50N");
+        assert_eq!(coord(true, 49_058_334, 1), "4903.5 N");
+        assert_eq!(coord(true, 49_058_334, 3), "490 .  N");
+        assert_eq!(coord(false, -72_029_167, 4), "072  .  W");
+        assert_eq!(coord(false, -72_029_167, 9), "072  .  W");
+    }
+
+    #[test]
+    fn proportional_paths_thin_out_with_hops() {
+        let mut hops = [0; 3];
+        for n in 0..64 {
+            let path = proportional_path(n);
+            hops[path.split(',').filter(|h| !h.is_empty()).count()] += 1;
+        }
+        assert_eq!(hops, [32, 24, 8]);
+        assert_eq!(proportional_path(0), "WIDE1-1,WIDE2-1");
+    }
+
+    #[test]
+    fn object_and_telemetry_reports() {
+        let object = ObjectReport {
+            name: heapless::String::try_from("HAMFEST").unwrap(),
+            timestamp: Timestamp::Dhm { day: 5, hour: 14, minute: 7 },
+            latitude: Coordinate { microdegrees: 49_058_334 },
+            longitude: Coordinate { microdegrees: -72_029_167 },
+            symbol_table: '/',
+            symbol_code: 'E',
+            comment: Some(heapless::String::try_from("Sat 9-4").unwrap()),
+        };
+        let frame = build_object_frame(&object, "").unwrap();
+        assert_eq!(info(&frame), b";HAMFEST  *051407z4903.50N/07201.75WESat 9-4");
+
+        let telemetry = Telemetry { seq: 1005, analog: [7, 12, 0, 255, 3], digital: 0b101 };
+        let frame = build_telemetry_frame(&telemetry, "").unwrap();
+        assert_eq!(info(&frame), b"T#005,007,012,000,255,003,00000101");
+    }
+}
@@ -1,20 +1,8 @@
 use heapless::Vec;
 
-use crate::bitstream::Bitstream;
-
-pub const BEGIN_FLAGS: usize = 75;
-pub const END_FLAGS: usize = 3;
 pub const MAX_DIGIPEATERS: usize = 8;
 pub const MAX_INFO_LEN: usize = 256;
 pub const MAX_FRAME_LEN: usize = 330;
-pub const MAX_TX_BITS: usize = {
-    let d = 8 * MAX_FRAME_LEN; // raw data bits
-    let s = d / 5;             // worst-case stuffed bits
-    d + s + 8 * (BEGIN_FLAGS + END_FLAGS)
-};
-pub const MAX_TX_BYTES: usize = (MAX_TX_BITS + 7) / 8;
-
-pub type TxBits = Bitstream<MAX_TX_BYTES>;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct AddressField {
@@ -28,7 +16,6 @@ impl AddressField {
         })
     }
 
-    #[allow(dead_code)]
     pub const fn from_raw(raw: [u8; 7]) -> Self {
         Self { raw }
     }
@@ -36,6 +23,24 @@ impl AddressField {
     pub fn raw(&self) -> [u8; 7] {
         self.raw
     }
+
+    /// Callsign characters (space padded) and SSID.
+    pub fn callsign(&self) -> ([u8; 6], u8) {
+        let mut call = [b' '; 6];
+        for (c, &r) in call.iter_mut().zip(self.raw.iter()) {
+            *c = r >> 1;
+        }
+        (call, (self.raw[6] >> 1) & 0x0F)
+    }
+}
+
+/// Link-layer framing used to put a frame on the air.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Framing {
+    /// HDLC flags, bit-stuffing and FCS.
+    Ax25,
+    /// IL2P sync word, scrambling and Reed-Solomon parity.
+    Il2p,
 }
 
 fn encode_callsign(call: &str, ssid: u8) -> Result<[u8; 7], ()> {
@@ -62,7 +67,7 @@ fn encode_callsign(call: &str, ssid: u8) -> Result<[u8; 7], ()> {
     Ok(out)
 }
 
-fn crc16(data: &[u8]) -> u16 {
+pub(crate) fn crc16(data: &[u8]) -> u16 {
     let mut crc: u16 = 0xFFFF;
 
     for &byte in data {
@@ -122,60 +127,98 @@ pub fn build_ui_frame(
 
     frame.extend_from_slice(info).map_err(|_| ())?;
 
-    let crc = crc16(&frame);
-    frame.push((crc & 0xFF) as u8).map_err(|_| ())?;
-    frame.push((crc >> 8) as u8).map_err(|_| ())?;
+    push_fcs(&mut frame)?;
 
     Ok(frame)
 }
 
-/// Builds the full on-air bitstream: BEGIN_FLAGS + stuffed frame + END_FLAGS.
-pub fn build_on_air(
-    frame: Vec<u8, MAX_FRAME_LEN>,
-) -> Result<TxBits, ()> {
-    let mut bs = Bitstream::new();
-    write_flags(&mut bs, BEGIN_FLAGS)?;
-    write_frame_stuffed(&mut bs, frame.as_slice())?;
-    write_flags(&mut bs, END_FLAGS)?;
-    bs.finish()?;
-    Ok(bs)
+/// Appends the frame check sequence over everything already in `frame`.
+pub(crate) fn push_fcs(frame: &mut Vec<u8, MAX_FRAME_LEN>) -> Result<(), ()> {
+    let crc = crc16(frame);
+    frame.push((crc & 0xFF) as u8).map_err(|_| ())?;
+    frame.push((crc >> 8) as u8).map_err(|_| ())
 }
 
-#[inline]
-fn push_byte_raw(bs: &mut TxBits, b: u8) -> Result<(), ()> {
-    // AX.25/HDLC sends LSB-first
-    for i in 0..8 {
-        bs.push_bit(((b >> i) & 1) != 0)?;
-    }
-    Ok(())
+/// Shortest frame worth checking: two addresses, control and FCS.
+const MIN_FRAME_LEN: usize = 2 * 7 + 1 + 2;
+
+/// HDLC deframer: finds flags, removes bit-stuffing and checks the FCS.
+///
+/// Takes NRZI-decoded bits in the order they were received and hands back
+/// frames in the same form `build_ui_frame` produces, FCS included.
+pub struct Deframer {
+    frame: Vec<u8, MAX_FRAME_LEN>,
+    pattern: u8,
+    acc: u8,
+    nbits: u8,
+    synced: bool,
 }
 
-fn write_flags(bs: &mut TxBits, count: usize) -> Result<(), ()> {
-    for _ in 0..count {
-        push_byte_raw(bs, 0x7E)?;
+impl Deframer {
+    pub fn new() -> Self {
+        Self {
+            frame: Vec::new(),
+            pattern: 0,
+            acc: 0,
+            nbits: 0,
+            synced: false,
+        }
     }
-    Ok(())
-}
 
-/// Write the frame with HDLC bit-stuffing (insert 0 after any run of five 1s)
-fn write_frame_stuffed(bs: &mut TxBits, frame: &[u8]) -> Result<(), ()> {
-    let mut ones: u8 = 0;
-    for &b in frame {
-        for i in 0..8 {
-            let bit = ((b >> i) & 1) != 0;
-            bs.push_bit(bit)?;
-            if bit {
-                ones += 1;
-                if ones == 5 {
-                    bs.push_bit(false)?; // stuffed 0
-                    ones = 0;
-                }
-            } else {
-                ones = 0;
+    /// Feed one received bit. Returns a frame when a closing flag completes
+    /// one with a valid FCS.
+    pub fn push_bit(&mut self, bit: bool) -> Option<Vec<u8, MAX_FRAME_LEN>> {
+        // Last eight bits, newest in the MSB
+        self.pattern = (self.pattern >> 1) | ((bit as u8) << 7);
+
+        if self.pattern == 0x7E {
+            // Flag. Seven of its bits went into the accumulator, so a
+            // byte-aligned frame leaves exactly seven behind.
+            let done = self.synced && self.nbits == 7 && self.frame.len() >= MIN_FRAME_LEN;
+            let frame = core::mem::take(&mut self.frame);
+            self.acc = 0;
+            self.nbits = 0;
+            self.synced = true;
+
+            if done && fcs_ok(&frame) {
+                return Some(frame);
+            }
+            return None;
+        }
+
+        if self.pattern & 0xFE == 0xFE {
+            // Seven ones: abort, or just idle/noise
+            self.synced = false;
+            return None;
+        }
+
+        if self.pattern & 0xFC == 0x7C {
+            // Zero after five ones: stuffed, drop it
+            return None;
+        }
+
+        if !self.synced {
+            return None;
+        }
+
+        self.acc = (self.acc >> 1) | ((bit as u8) << 7);
+        self.nbits += 1;
+        if self.nbits == 8 {
+            if self.frame.push(self.acc).is_err() {
+                // Too long for us, wait for the next flag
+                self.frame.clear();
+                self.synced = false;
             }
+            self.nbits = 0;
         }
+
+        None
     }
-    Ok(())
+}
+
+fn fcs_ok(frame: &[u8]) -> bool {
+    let (data, fcs) = frame.split_at(frame.len() - 2);
+    crc16(data) == u16::from_le_bytes([fcs[0], fcs[1]])
 }
 
 #[cfg(test)]
@@ -204,9 +247,10 @@ mod tests {
             comment: Some(comment),
             timestamp: None,
             messaging: false,
+            ambiguity: 0,
         };
 
-        let frame = crate::aprs::build_position_frame(&report).expect("frame build");
+        let frame = crate::aprs::build_position_frame(&report, "WIDE1-1").expect("frame build");
 
         let expected: [u8; 63] = [
             130, 160, 180, 64, 64, 64, 96, 156, 96, 134, 130, 152, 152, 110, 174, 146, 136, 138, 98,
@@ -1,66 +1,399 @@
+use core::fmt::Write;
+
+use defmt::println;
+
 use crate::app::Shared;
-use crate::aprs::{self, Coordinate};
-use crate::ax25::{self, TxBits};
+use crate::aprs::{self, Coordinate, ObjectReport, Telemetry};
+use crate::ax25;
+use crate::config::{BeaconProfile, BeaconRate, FixFallback, PathStrategy, ProfileEvery, ProfileKind, ZoneAction, MAX_PROFILES};
+use crate::decay::Decay;
+use crate::fixquality::{self, FixQuality, Shortfall};
+use crate::geofence;
+use crate::gpstime;
+use crate::hardware::audio;
+use crate::input::Event;
+use crate::lastfix::{self, LastFix};
+use crate::onair::TxFrame;
 use crate::sched::Tickable;
+use crate::smartbeacon::{self, SmartBeacon};
+
+/// How often to look for a beacon asked for with the button.
+const EVENT_POLL_MS: u64 = 50;
 
 pub struct BeaconTask {
     next_tx_time: u64,
+    next_poll_at: u64,
+    /// The beacon that's due was asked for with the button: it goes out
+    /// without waiting for its slot or SmartBeaconing, and sends our
+    /// position only.
+    forced: bool,
+    /// When frames were last queued.
+    last_tx: Option<u64>,
+    smart: SmartBeacon,
+    /// Retries so far for the beacon that's due.
+    attempt: u8,
+    /// Uptime of the next slot, in slotted mode.
+    slot_at: Option<u64>,
+    /// Beacon cycles so far, for profiles sent every so many.
+    cycle: u32,
+    /// When each profile was last sent.
+    last_sent: [Option<u64>; MAX_PROFILES],
+    telemetry_seq: u16,
+    /// Position beacons sent, for proportional pathing.
+    position_seq: u32,
+    decay: Decay,
+}
+
+/// What our position profiles send this cycle.
+enum Beacon {
+    Position { fix: LastFix, stale: bool },
+    /// Status text only, when there's no position worth sending.
+    Status,
+    Skip,
 }
 
 impl BeaconTask {
     pub fn new() -> Self {
         Self {
             next_tx_time: 0,
+            next_poll_at: 0,
+            forced: false,
+            last_tx: None,
+            smart: SmartBeacon::new(),
+            attempt: 0,
+            slot_at: None,
+            cycle: 0,
+            last_sent: [None; MAX_PROFILES],
+            telemetry_seq: 0,
+            position_seq: 0,
+            decay: Decay::new(),
+        }
+    }
+
+    /// Sends a beacon at once, unless the last was too recent.
+    fn beacon_now(&mut self, now: u64, shared: &Shared) {
+        let spacing_ms = shared.config.beacon.min_spacing_s as u64 * 1_000;
+        if self.last_tx.is_some_and(|at| now - at < spacing_ms) {
+            println!("Beacon: asked for too soon after the last, ignored");
+            return;
         }
+        println!("Beacon: asked for");
+        self.forced = true;
+        self.attempt = 0;
+        self.next_tx_time = now;
     }
 
     fn run(&mut self, now: u64, shared: &mut Shared) {
+        // Is the current fix good enough to send?
+        let quality = FixQuality::from_nmea(&shared.nmea, shared.last_fix.age_s(now, None), shared.fix_3d);
+        let checked = fixquality::check(&shared.config.fix_quality, &quality);
+        let live = checked.ok().and_then(|_| LastFix::from_nmea(&shared.nmea));
+
+        // In slotted mode, wait for the next slot by GPS time
+        if !self.forced
+            && let BeaconRate::Slotted { interval_s, offset_s } = shared.config.beacon.rate
+        {
+            if self.slot_at.is_none() {
+                let Some(utc) = shared.clock.utc_ms(now) else {
+                    // No time from the GPS yet
+                    self.next_tx_time = now + 1_000;
+                    return;
+                };
+                self.slot_at = shared.clock.uptime_at(gpstime::next_slot(utc, interval_s, offset_s));
+            }
+            if let Some(at) = self.slot_at
+                && now < at
+            {
+                self.next_tx_time = at;
+                return;
+            }
+        }
 
-        if shared.nmea.fix_type.is_none() {
-            // No fix, try again later.
-            self.next_tx_time = now + 5_000; // 5 sec.
+        // With SmartBeaconing, check every second whether speed or a corner
+        // has made a beacon due. Without a good fix we're as good as parked.
+        let (speed_kmh, course) = match live {
+            Some(fix) => (smartbeacon::knots_to_kmh(shared.nmea.speed_over_ground.unwrap_or(0.0)), fix.course),
+            None => (0, None),
+        };
+        if !self.forced
+            && let BeaconRate::Smart(cfg) = &shared.config.beacon.rate
+            && !self.smart.due(now, cfg, speed_kmh, course)
+        {
+            self.next_tx_time = now + 1_000;
             return;
         }
 
-        // Only proceed when all info is present.
-        let (lat, lon) = match (shared.nmea.latitude(), shared.nmea.longitude()) {
-            (Some(lat), Some(lon)) => (lat, lon),
-            _ => {
-                // We have a fix, but not everything else; try shortly
-                self.next_tx_time = now + 1_000;
-                return;
+        // Which profiles this cycle sends
+        let mut due = [false; MAX_PROFILES];
+        for (i, profile) in shared.config.beacon.profiles.iter().enumerate() {
+            due[i] = if self.forced {
+                matches!(profile.kind, ProfileKind::Position)
+            } else {
+                match profile.every {
+                    ProfileEvery::Cycles(n) => self.cycle.is_multiple_of(n.max(1) as u32),
+                    ProfileEvery::Seconds(s) => self.last_sent[i].is_none_or(|at| now - at >= s as u64 * 1_000),
+                }
+            };
+        }
+        let wants_fix = shared.config.beacon.profiles.iter()
+            .zip(due)
+            .any(|(p, due)| due && matches!(p.kind, ProfileKind::Position));
+
+        // Send a good fix. Otherwise try again a few times before falling
+        // back on what the config says.
+        let beacon = match live {
+            Some(fix) => Beacon::Position { fix, stale: false },
+            None if !wants_fix => Beacon::Skip,
+            None => {
+                let why = checked.err().unwrap_or(Shortfall::NoFix).as_str();
+                let cfg = &shared.config.fix_quality;
+                if self.attempt < cfg.attempts {
+                    self.attempt += 1;
+
+                    // Slotted beacons keep to their slots, so try again at
+                    // the next one, which the check above will find
+                    if !self.forced
+                        && let BeaconRate::Slotted { .. } = shared.config.beacon.rate
+                    {
+                        println!("Beacon: {}, retry {} of {} at the next slot", why, self.attempt, cfg.attempts);
+                        self.slot_at = None;
+                        self.next_tx_time = now + 1_000;
+                        return;
+                    }
+
+                    let wait = fixquality::backoff_ms(cfg, self.attempt);
+                    println!("Beacon: {}, retry {} of {} in {} ms", why, self.attempt, cfg.attempts, wait);
+                    self.next_tx_time = now + wait as u64;
+                    return;
+                }
+
+                match (cfg.fallback, shared.last_fix.fix) {
+                    (FixFallback::SendStale, Some(fix)) => {
+                        println!("Beacon: {}, sending last known position", why);
+                        let utc_now = shared.clock.utc_ms(now).map(|ms| ms / 1_000);
+                        Beacon::Position { fix, stale: shared.last_fix.is_stale(&shared.config.last_fix, now, utc_now) }
+                    }
+                    (FixFallback::SendWithoutPosition, _) => {
+                        println!("Beacon: {}, sending status only", why);
+                        Beacon::Status
+                    }
+                    _ => {
+                        println!("Beacon: {}, skipped", why);
+                        Beacon::Skip
+                    }
+                }
             }
         };
 
-        // Prepare the packet
-        shared.pos_rpt.latitude = Coordinate::from_float(lat);
-        shared.pos_rpt.longitude = Coordinate::from_float(lon);
+        // Inside a zone, do as it says. Without a position to go on, use the
+        // last one we had.
+        let here = match beacon {
+            Beacon::Position { fix, .. } => Some(fix),
+            _ => shared.last_fix.fix,
+        };
+        let zone = here.and_then(|fix| geofence::find(&shared.config.zones, fix.latitude, fix.longitude));
+        if let Some(zone) = zone
+            && let ZoneAction::Quiet = zone.action
+        {
+            println!("Beacon: in quiet zone {}, skipped", zone.name.as_str());
+            self.schedule_next(now, shared, course, None);
+            return;
+        }
+        let action = zone.map(|z| &z.action);
+
+        // Encode each frame as bytes; the modem generates the on-air bits
+        let mut frames = heapless::Vec::<(usize, TxFrame), MAX_PROFILES>::new();
+        for (i, profile) in shared.config.beacon.profiles.iter().enumerate() {
+            if !due[i] {
+                continue;
+            }
+            if let Some(packet) = self.build(now, shared, profile, &beacon, live.as_ref(), action) {
+                let frame = TxFrame::new(shared.config.modem.framing, packet).expect("build tx frame");
+                let _ = frames.push((i, frame));
+            }
+        }
 
-        // If modem hasn't consumed the previous frames, reschedule
-        if shared.txq.is_full() {
+        // If modem hasn't room for them all, reschedule
+        if shared.txq.capacity() - shared.txq.len() < frames.len() {
             self.next_tx_time = now + 1_000;
             return;
         }
 
-        // Build bytes -> stuffed bits as Bitstream
-        // Encode the packet as bytes
-        let packet = aprs::build_position_frame(&shared.pos_rpt).expect("build frame");
-        let bits: TxBits = ax25::build_on_air(packet).expect("build bitstream");
-        
-        // Send it off to the modem
-        shared.txq.push_back(bits).ok();
+        // Send them off to the modem
+        let mut position = None;
+        for (i, frame) in frames {
+            shared.txq.push_back(frame).ok();
+            self.last_sent[i] = Some(now);
+            self.last_tx = Some(now);
+
+            // Sequence numbers only move on for frames that went out
+            match (&shared.config.beacon.profiles[i].kind, &beacon) {
+                (ProfileKind::Position, Beacon::Position { fix, .. }) => {
+                    position = Some((fix.latitude, fix.longitude));
+                    self.position_seq = self.position_seq.wrapping_add(1);
+                }
+                (ProfileKind::Telemetry, _) => self.telemetry_seq = (self.telemetry_seq + 1) % 1000,
+                _ => {}
+            }
+        }
+        // A beacon asked for doesn't put the other profiles out of step
+        if !self.forced {
+            self.cycle = self.cycle.wrapping_add(1);
+        }
+
+        // Home is quieter still
+        let home_s = match action {
+            Some(&ZoneAction::Home { interval_s, .. }) => Some(interval_s),
+            _ => None,
+        };
+        self.schedule_next(now, shared, course, position);
+        if let Some(interval_s) = home_s {
+            self.next_tx_time = self.next_tx_time.max(now + interval_s as u64 * 1_000);
+        }
+    }
+
+    /// The frame `profile` sends this cycle, if any, changed as the zone
+    /// we're in says.
+    fn build(
+        &mut self,
+        now: u64,
+        shared: &Shared,
+        profile: &BeaconProfile,
+        beacon: &Beacon,
+        live: Option<&LastFix>,
+        zone: Option<&ZoneAction>,
+    ) -> Option<heapless::Vec<u8, { ax25::MAX_FRAME_LEN }>> {
+        let comment = Some(profile.comment.clone()).filter(|c| !c.is_empty());
+        let zone_path = match zone {
+            Some(ZoneAction::Path(path)) => Some(path.as_str()),
+            _ => None,
+        };
+        let path = zone_path.unwrap_or(&profile.path);
+        let (symbol_table, symbol_code) = match zone {
+            Some(&ZoneAction::Symbol { table, code }) => (table, code),
+            _ => (shared.pos_rpt.symbol_table, shared.pos_rpt.symbol_code),
+        };
+
+        let packet = match (&profile.kind, beacon) {
+            (ProfileKind::Position, Beacon::Position { fix, stale }) => {
+                let mut rpt = shared.pos_rpt.clone();
+                rpt.latitude = Coordinate { microdegrees: fix.latitude };
+                rpt.longitude = Coordinate { microdegrees: fix.longitude };
+                rpt.comment = comment;
+                rpt.symbol_table = symbol_table;
+                rpt.symbol_code = symbol_code;
+                if let Some(&ZoneAction::Home { ambiguity, .. }) = zone {
+                    rpt.ambiguity = ambiguity;
+                }
+
+                // A stale position goes out marked as the config says, if at all
+                if *stale && !lastfix::mark_stale(&mut rpt, fix, shared.config.last_fix.stale) {
+                    println!("Beacon: last known position is stale, skipped");
+                    return None;
+                }
+                let path = match (zone_path, shared.config.beacon.path) {
+                    (Some(path), _) => path,
+                    (None, PathStrategy::Profile) => &profile.path,
+                    (None, PathStrategy::Proportional) => aprs::proportional_path(self.position_seq),
+                };
+                aprs::build_position_frame(&rpt, path)
+            }
+            (ProfileKind::Position, Beacon::Status) => aprs::build_status_frame(&profile.comment, path),
+            (ProfileKind::Position, Beacon::Skip) => return None,
+            (ProfileKind::Status, _) => {
+                let mut text = heapless::String::<64>::new();
+                if let Some(fix) = live {
+                    let _ = write!(
+                        text,
+                        "{}{}{} ",
+                        aprs::maidenhead(fix.latitude, fix.longitude),
+                        symbol_table,
+                        symbol_code,
+                    );
+                }
+                let _ = text.push_str(&profile.comment);
+                aprs::build_status_frame(text.trim_end(), path)
+            }
+            (ProfileKind::Telemetry, _) => {
+                let telemetry = Telemetry {
+                    seq: self.telemetry_seq,
+                    analog: [
+                        shared.nmea.num_of_fix_satellites.unwrap_or(0).min(255) as u8,
+                        (shared.nmea.hdop.unwrap_or(0.0) * 10.0) as u8,
+                        smartbeacon::knots_to_kmh(shared.nmea.speed_over_ground.unwrap_or(0.0)).min(255) as u8,
+                        // Tens of metres
+                        (shared.nmea.altitude.unwrap_or(0.0) / 10.0) as u8,
+                        audio::tx_faults().min(255) as u8,
+                    ],
+                    digital: (live.is_some() as u8)
+                        | ((shared.fix_3d == Some(true)) as u8) << 1
+                        | (audio::tx_locked_out() as u8) << 2,
+                };
+                aprs::build_telemetry_frame(&telemetry, path)
+            }
+            (ProfileKind::Object(obj), _) => {
+                // Objects need a time, so wait for the GPS to give one
+                let utc = shared.clock.utc_ms(now)?;
+                let report = ObjectReport {
+                    name: obj.name.clone(),
+                    timestamp: gpstime::dhm(utc / 1_000)?,
+                    latitude: Coordinate { microdegrees: obj.latitude },
+                    longitude: Coordinate { microdegrees: obj.longitude },
+                    symbol_table: obj.symbol_table,
+                    symbol_code: obj.symbol_code,
+                    comment,
+                };
+                aprs::build_object_frame(&report, path)
+            }
+        };
+        Some(packet.expect("build frame"))
+    }
 
-        // Schedule the next beacon
-        self.next_tx_time = now + (30 * 60 * 1_000); // 30 min
+    /// Schedules the next beacon after a cycle at `now`, which sent our
+    /// `position` if it's given.
+    fn schedule_next(&mut self, now: u64, shared: &mut Shared, course: Option<u16>, position: Option<(i32, i32)>) {
+        self.attempt = 0;
+        self.forced = false;
+        self.next_tx_time = match shared.config.beacon.rate {
+            BeaconRate::Fixed { interval_s } => now + interval_s as u64 * 1_000,
+            BeaconRate::Slotted { .. } => {
+                // Work out the next slot once this one has passed
+                self.slot_at = None;
+                now + 1_000
+            }
+            BeaconRate::Jittered { interval_s, jitter_s } => {
+                // More jitter than interval would mean nothing
+                let jitter_s = jitter_s.min(interval_s) as u64;
+                shared.rng.mix(now as u32);
+                let jitter = (shared.rng.next_u32() as u64 % (2 * jitter_s + 1)) as i64 - jitter_s as i64;
+                let interval_ms = (interval_s as i64 + jitter).max(1) * 1_000;
+                now + interval_ms as u64
+            }
+            BeaconRate::Smart(_) => {
+                self.smart.sent(now, course);
+                now + 1_000
+            }
+            BeaconRate::Decay { interval_s, max_interval_s, still_m } => {
+                now + self.decay.sent(position, interval_s, max_interval_s, still_m) as u64 * 1_000
+            }
+        };
     }
 }
 
 impl Tickable for BeaconTask {
     fn next_run_at(&self) -> u64 {
-        self.next_tx_time
+        self.next_tx_time.min(self.next_poll_at)
     }
 
     fn tick(&mut self, now: u64, shared: &mut Shared) {
-        self.run(now, shared);
+        // Look for the button every so often, between beacons
+        self.next_poll_at = now + EVENT_POLL_MS;
+        if shared.events.take(Event::BeaconNow) {
+            self.beacon_now(now, shared);
+        }
+
+        if now >= self.next_tx_time {
+            self.run(now, shared);
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,398 @@
+//! Runtime configuration.
+//!
+//! Settings that differ between radios or trips live here rather than in
+//! `co`, so they can be changed without re-flashing once there is a menu to
+//! edit them.
+
+use crate::aprs::Path;
+use crate::ax25::Framing;
+use crate::hardware::audio;
+use crate::modem::ModemProfile;
+
+#[derive(Default)]
+pub struct Config {
+    pub beacon: BeaconConfig,
+    pub last_fix: LastFixConfig,
+    pub fix_quality: FixQualityConfig,
+    /// Areas where beacons behave differently. The first that contains our
+    /// position applies.
+    pub zones: heapless::Vec<Zone, MAX_ZONES>,
+    pub modem: ModemConfig,
+    pub cw_id: CwIdConfig,
+    pub ptt: PttConfig,
+    pub input: InputConfig,
+}
+
+/// Most beacon profiles there can be.
+pub const MAX_PROFILES: usize = 4;
+
+/// Most geofence zones there can be, and corners each can have.
+pub const MAX_ZONES: usize = 4;
+pub const MAX_VERTICES: usize = 8;
+
+pub struct BeaconConfig {
+    /// When a beacon cycle comes round.
+    pub rate: BeaconRate,
+    /// What goes out on the cycles, each when it's due.
+    pub profiles: heapless::Vec<BeaconProfile, MAX_PROFILES>,
+    /// Digipeater path for position beacons.
+    pub path: PathStrategy,
+    /// Shortest time from the last beacon to one asked for with the
+    /// button, so it can't be leant on.
+    pub min_spacing_s: u32,
+}
+
+/// How position beacons pick their digipeater path.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PathStrategy {
+    /// The profile's own path, every time.
+    Profile,
+    /// Proportional pathing: most beacons direct, fewer through one hop,
+    /// and fewest through two. See `aprs::proportional_path`.
+    Proportional,
+}
+
+impl Default for BeaconConfig {
+    fn default() -> Self {
+        let mut profiles = heapless::Vec::new();
+        let _ = profiles.push(BeaconProfile {
+            kind: ProfileKind::Position,
+            every: ProfileEvery::Cycles(1),
+            path: Path::try_from("WIDE1-1").unwrap(),
+            comment: heapless::String::try_from("github.com/anthonydotmoe/pico-aprs-beacon").unwrap(),
+        });
+
+        Self {
+            rate: BeaconRate::Fixed { interval_s: 30 * 60 },
+            profiles,
+            path: PathStrategy::Profile,
+            min_spacing_s: 30,
+        }
+    }
+}
+
+/// One kind of periodic transmission.
+pub struct BeaconProfile {
+    pub kind: ProfileKind,
+    pub every: ProfileEvery,
+    pub path: Path,
+    pub comment: heapless::String<43>,
+}
+
+pub enum ProfileKind {
+    /// Our position, subject to the fix quality rules.
+    Position,
+    /// Status text, led by our grid square when there's a good fix.
+    Status,
+    /// Satellites, HDOP, speed, altitude and transmit faults.
+    Telemetry,
+    /// A fixed object, such as an event.
+    Object(ObjectConfig),
+}
+
+pub struct ObjectConfig {
+    pub name: heapless::String<9>,
+    /// Microdegrees.
+    pub latitude: i32,
+    pub longitude: i32,
+    pub symbol_table: char,
+    pub symbol_code: char,
+}
+
+/// How often a profile is sent.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProfileEvery {
+    /// Every so many beacon cycles, starting with the first.
+    Cycles(u16),
+    /// On the first cycle at least so many seconds after it was last sent.
+    Seconds(u32),
+}
+
+/// How often position beacons go out.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BeaconRate {
+    /// Every so many seconds, moving or not.
+    Fixed { interval_s: u32 },
+    /// Every `interval_s`, at `offset_s` into each interval of GPS time, so
+    /// stations given different offsets never collide. Waits for the GPS
+    /// to give the time.
+    Slotted { interval_s: u32, offset_s: u32 },
+    /// Every `interval_s`, give or take up to `jitter_s` at random, so
+    /// stations that started together drift apart.
+    Jittered { interval_s: u32, jitter_s: u32 },
+    /// From speed and course, for mobile use.
+    Smart(SmartBeaconConfig),
+    /// For fixed stations: the interval doubles after each beacon from
+    /// within `still_m` of the last, up to `max_interval_s`.
+    Decay { interval_s: u32, max_interval_s: u32, still_m: u16 },
+}
+
+/// SmartBeaconing parameters. See `smartbeacon` for how they're used.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SmartBeaconConfig {
+    /// At and above this speed, beacon at `fast_rate_s`.
+    pub fast_speed_kmh: u16,
+    pub fast_rate_s: u16,
+    /// At and below this speed, beacon at `slow_rate_s` and ignore turns.
+    pub slow_speed_kmh: u16,
+    pub slow_rate_s: u16,
+    /// Smallest change of course that counts as a corner.
+    pub turn_min_deg: u16,
+    /// Added to `turn_min_deg` divided by speed, in degrees × km/h, so
+    /// slow turns need to be sharper.
+    pub turn_slope: u16,
+    /// No corner beacons sooner than this after the last beacon.
+    pub turn_time_s: u16,
+}
+
+impl Default for SmartBeaconConfig {
+    fn default() -> Self {
+        Self {
+            fast_speed_kmh: 90,
+            fast_rate_s: 180,
+            slow_speed_kmh: 5,
+            slow_rate_s: 30 * 60,
+            turn_min_deg: 28,
+            turn_slope: 240,
+            turn_time_s: 15,
+        }
+    }
+}
+
+/// A named area, and what beacons do inside it.
+pub struct Zone {
+    pub name: heapless::String<16>,
+    pub shape: Shape,
+    pub action: ZoneAction,
+}
+
+/// Where a zone is. Positions in microdegrees.
+pub enum Shape {
+    Circle { latitude: i32, longitude: i32, radius_m: u32 },
+    /// Corners in order, (latitude, longitude). The last joins the first.
+    Polygon(heapless::Vec<(i32, i32), MAX_VERTICES>),
+}
+
+/// How beacons change inside a zone.
+pub enum ZoneAction {
+    /// Nothing is sent at all.
+    Quiet,
+    /// Positions go out with `ambiguity` digits blanked (see
+    /// `PositionReport`), no more often than every `interval_s`.
+    Home { ambiguity: u8, interval_s: u32 },
+    /// Every frame goes through this digipeater path instead.
+    Path(Path),
+    /// Positions and status go out with this symbol instead.
+    Symbol { table: char, code: char },
+}
+
+/// What to beacon once the GPS has lost its fix.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StalePolicy {
+    /// Nothing, until there's a fix again.
+    Skip,
+    /// The last position, with the time it was fixed. Skipped if that time
+    /// isn't known.
+    Timestamp,
+    /// The last position, with a marker in front of the comment.
+    Comment,
+}
+
+/// Last known position, kept in flash across power cycles.
+pub struct LastFixConfig {
+    /// How often to save a changed position. Each save erases a flash
+    /// sector, so not too often.
+    pub save_interval_s: u32,
+    /// A position older than this is stale, and sent as `stale` says.
+    /// Younger ones go out as though the fix were current.
+    pub stale_after_s: u32,
+    pub stale: StalePolicy,
+}
+
+impl Default for LastFixConfig {
+    fn default() -> Self {
+        Self {
+            save_interval_s: 10 * 60,
+            stale_after_s: 60,
+            stale: StalePolicy::Timestamp,
+        }
+    }
+}
+
+/// What to do when the fix still isn't good enough after every retry.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FixFallback {
+    /// The last good position, as `LastFixConfig` says.
+    SendStale,
+    /// A status report with the comment, and no position.
+    SendWithoutPosition,
+    /// Nothing, until the next beacon is due.
+    Skip,
+}
+
+/// How good a fix has to be to beacon, and how hard to try for one.
+pub struct FixQualityConfig {
+    /// Longest since the last good position came in.
+    pub max_age_s: u32,
+    /// Fewest satellites in the fix.
+    pub min_satellites: u8,
+    /// Highest HDOP, in tenths. 0 for no limit.
+    pub max_hdop_x10: u16,
+    /// Highest PDOP, in tenths. 0 for no limit.
+    pub max_pdop_x10: u16,
+    /// Turn down 2D fixes.
+    pub require_3d: bool,
+    /// Retries when a beacon is due but the fix isn't good enough.
+    pub attempts: u8,
+    /// Wait before the first retry, doubling for each one after.
+    pub retry_ms: u32,
+    pub fallback: FixFallback,
+}
+
+impl Default for FixQualityConfig {
+    fn default() -> Self {
+        Self {
+            max_age_s: 5,
+            min_satellites: 4,
+            max_hdop_x10: 50,
+            max_pdop_x10: 0,
+            require_3d: false,
+            attempts: 5,
+            retry_ms: 2_000,
+            fallback: FixFallback::SendStale,
+        }
+    }
+}
+
+pub struct ModemConfig {
+    /// Baud rate and tones, for both directions.
+    pub profile: ModemProfile,
+    /// Framing used for outgoing frames.
+    pub framing: Framing,
+    /// Time from PTT key-up to the start of the frame, sent as flags.
+    pub txdelay_ms: u16,
+    /// Time after the end of the frame before PTT is released, sent as flags.
+    pub txtail_ms: u16,
+    /// CSMA slot: how long to wait between attempts to grab a clear channel.
+    pub slottime_ms: u16,
+    /// p-persistence: on a clear channel, key up with probability
+    /// (persist + 1) / 256 each slot.
+    pub persist: u8,
+    /// Give up on a frame if the channel stays busy this long.
+    pub max_defer_ms: u32,
+    /// Longest PTT may stay keyed, whatever is being sent, before it is
+    /// forced off.
+    pub tx_timeout_ms: u32,
+    /// How long transmit stays locked out after a timeout.
+    pub tx_lockout_ms: u32,
+    /// Output level, Q15 of full scale.
+    pub tx_level: u16,
+    /// Space tone level relative to mark, in dB. Positive makes space
+    /// louder. Limited to ±12 dB.
+    pub twist_db: i8,
+    /// Tilt the tones 6 dB/octave, for radios that take audio after their
+    /// pre-emphasis stage.
+    pub preemphasis: bool,
+    /// Voice Alert: CTCSS tone under the packets and CW ID, in 0.1 Hz
+    /// (1000 for 100 Hz). 0 for none.
+    pub ctcss_dhz: u16,
+    /// CTCSS tone level, Q15 of full scale. The packet tones are turned
+    /// down by as much, so the sum can't clip.
+    pub ctcss_level: u16,
+}
+
+impl Default for ModemConfig {
+    fn default() -> Self {
+        Self {
+            profile: ModemProfile::BELL_202,
+            framing: Framing::Ax25,
+            txdelay_ms: 500,
+            txtail_ms: 20,
+            slottime_ms: 100,
+            persist: 63,
+            max_defer_ms: 30_000,
+            tx_timeout_ms: audio::TX_TIMEOUT_MS,
+            tx_lockout_ms: audio::TX_LOCKOUT_MS,
+            tx_level: i16::MAX as u16,
+            twist_db: 0,
+            preemphasis: false,
+            ctcss_dhz: 0,
+            ctcss_level: 3277,
+        }
+    }
+}
+
+/// When to follow a transmission with a CW ID.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CwIdMode {
+    Off,
+    /// After every transmission.
+    AfterEach,
+    /// At the end of a transmission, if the last ID was at least this many
+    /// minutes ago.
+    EveryMinutes(u16),
+}
+
+/// Morse station ID, sent as a keyed tone before PTT drops.
+pub struct CwIdConfig {
+    pub mode: CwIdMode,
+    /// Sending speed, words per minute.
+    pub wpm: u8,
+    /// Tone pitch, sent at the modem's `tx_level`.
+    pub pitch_hz: u16,
+}
+
+impl Default for CwIdConfig {
+    fn default() -> Self {
+        Self {
+            mode: CwIdMode::Off,
+            wpm: 20,
+            pitch_hz: 800,
+        }
+    }
+}
+
+/// How the PTT line is driven.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PttDrive {
+    PushPull,
+    /// Only ever pulled low; otherwise left to the radio's pull-up.
+    OpenDrain,
+}
+
+/// PTT line and timing. The line settings are read once at start-up.
+pub struct PttConfig {
+    /// Key by pulling the line low, as HTs with a mic-jack PTT want.
+    pub active_low: bool,
+    pub drive: PttDrive,
+    /// Time from key-up to the first sample, for radios slow to get going.
+    /// Rounded up to whole audio buffers.
+    pub lead_ms: u16,
+    /// Time PTT stays keyed after the last sample. Rounded up likewise.
+    pub tail_ms: u16,
+}
+
+impl Default for PttConfig {
+    fn default() -> Self {
+        Self {
+            active_low: true,
+            drive: PttDrive::OpenDrain,
+            lead_ms: 0,
+            tail_ms: 0,
+        }
+    }
+}
+
+/// Buttons and other controls.
+pub struct InputConfig {
+    /// How long a switch must hold still before a change counts.
+    pub debounce_ms: u16,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 30,
+        }
+    }
+}
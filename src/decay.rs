@@ -0,0 +1,78 @@
+//! Decay beaconing, for fixed stations.
+//!
+//! Each beacon from where the last one was doubles the wait for the next,
+//! up to a cap, so a station that hasn't moved fades into the background.
+//! Moving starts again from the shortest interval.
+
+use crate::aprs::UDEG_PER_KM;
+
+pub struct Decay {
+    interval_s: u32,
+    /// Where we last stopped.
+    last: Option<(i32, i32)>,
+}
+
+impl Decay {
+    pub const fn new() -> Self {
+        Self {
+            interval_s: 0,
+            last: None,
+        }
+    }
+
+    /// Records a beacon from `position` (microdegrees latitude, longitude),
+    /// or none, and returns the wait in seconds until the next.
+    /// `still_m` is how far it may wander and still count as not moved.
+    pub fn sent(&mut self, position: Option<(i32, i32)>, interval_s: u32, max_interval_s: u32, still_m: u16) -> u32 {
+        if let Some(pos) = position {
+            let moved = self.last.is_none_or(|last| !within(last, pos, still_m));
+            // Keep where we stopped, so creeping off a little at a time adds up
+            if moved {
+                self.interval_s = interval_s;
+                self.last = Some(pos);
+            } else {
+                self.interval_s = self.interval_s.saturating_mul(2);
+            }
+        }
+        self.interval_s = self.interval_s.clamp(interval_s, max_interval_s.max(interval_s));
+        self.interval_s
+    }
+}
+
+/// Whether `b` is within `m` metres of `a`, north-south and east-west.
+/// Longitude is taken as if on the equator, which only makes the test
+/// stricter away from it.
+fn within(a: (i32, i32), b: (i32, i32), m: u16) -> bool {
+    let limit = m as i64 * UDEG_PER_KM / 1_000;
+    (a.0 as i64 - b.0 as i64).abs() <= limit && (a.1 as i64 - b.1 as i64).abs() <= limit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOME: (i32, i32) = (49_058_333, -72_029_166);
+
+    #[test]
+    fn doubles_while_still() {
+        let mut decay = Decay::new();
+        let waits: heapless::Vec<u32, 8> = (0..7)
+            .map(|_| decay.sent(Some(HOME), 600, 7_200, 50))
+            .collect();
+        assert_eq!(waits, [600, 1_200, 2_400, 4_800, 7_200, 7_200, 7_200]);
+
+        // Wandering a few metres doesn't count; moving off does
+        assert_eq!(decay.sent(Some((HOME.0 + 300, HOME.1 - 400)), 600, 7_200, 50), 7_200);
+        assert_eq!(decay.sent(Some((HOME.0 + 600, HOME.1)), 600, 7_200, 50), 600);
+    }
+
+    #[test]
+    fn cycles_without_a_position_keep_the_wait() {
+        let mut decay = Decay::new();
+        assert_eq!(decay.sent(None, 600, 7_200, 50), 600);
+        assert_eq!(decay.sent(Some(HOME), 600, 7_200, 50), 600);
+        assert_eq!(decay.sent(Some(HOME), 600, 7_200, 50), 1_200);
+        assert_eq!(decay.sent(None, 600, 7_200, 50), 1_200);
+        assert_eq!(decay.sent(Some(HOME), 600, 7_200, 50), 2_400);
+    }
+}
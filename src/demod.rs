@@ -0,0 +1,304 @@
+//! AFSK demodulator, for any of the modem profiles.
+//!
+//! Integer-only so it keeps up on the Cortex-M0+. Each sample is mixed
+//! against mark and space local oscillators and the products are summed
+//! over one bit time; whichever tone has more energy sets the line level. A
+//! digital PLL recovers the bit clock from level transitions, and the
+//! sampled levels feed both the HDLC and the IL2P deframers.
+//!
+//! The same transitions drive data carrier detect: a real signal keeps
+//! landing where the PLL expects it, noise lands anywhere.
+
+use heapless::Vec;
+
+use crate::ax25::{self, MAX_FRAME_LEN};
+use crate::il2p;
+use crate::modem::{sine, ModemProfile};
+
+/// Longest correlation window (one bit time) we keep samples for.
+pub const MAX_WINDOW: usize = 64;
+
+/// How far a level transition pulls the PLL phase towards zero (Q8).
+const PLL_INERTIA_Q8: i64 = 180;
+
+/// Transitions within this much PLL phase of zero (1/8 bit) count as on time.
+const DCD_WINDOW: u32 = 1 << 29;
+/// Carrier detect score limits and hysteresis. On-time transitions add one,
+/// late or early ones take one away.
+const DCD_MAX: i32 = 64;
+const DCD_ON: i32 = 32;
+const DCD_OFF: i32 = 16;
+/// Longest run without a transition a frame can have (an HDLC flag's six
+/// 1s); a quieter line loses score every bit.
+const DCD_QUIET_BITS: u32 = 7;
+
+/// Quarter cycle, to get cosine out of the sine table.
+const QUARTER: u32 = 1 << 30;
+
+/// Running sum over the correlation window.
+struct Boxcar {
+    buf: [i32; MAX_WINDOW],
+    sum: i32,
+}
+
+impl Boxcar {
+    fn new() -> Self {
+        Self { buf: [0; MAX_WINDOW], sum: 0 }
+    }
+
+    #[inline]
+    fn push(&mut self, pos: usize, v: i32) {
+        self.sum += v - self.buf[pos];
+        self.buf[pos] = v;
+    }
+}
+
+/// One tone's local oscillator and I/Q correlator.
+struct Correlator {
+    phase: u32,
+    step: u32,
+    i: Boxcar,
+    q: Boxcar,
+}
+
+impl Correlator {
+    fn new(freq: u32, sample_rate: u32) -> Self {
+        Self {
+            phase: 0,
+            step: (((freq as u64) << 32) / sample_rate as u64) as u32,
+            i: Boxcar::new(),
+            q: Boxcar::new(),
+        }
+    }
+
+    /// Mixes in one sample and returns the tone energy over the window.
+    #[inline]
+    fn push(&mut self, pos: usize, x: i16) -> i64 {
+        let x = x as i32;
+        self.i.push(pos, (x * sine(self.phase.wrapping_add(QUARTER)) as i32) >> 15);
+        self.q.push(pos, (x * sine(self.phase) as i32) >> 15);
+        self.phase = self.phase.wrapping_add(self.step);
+
+        let (i, q) = (self.i.sum as i64, self.q.sum as i64);
+        i * i + q * q
+    }
+}
+
+pub struct Demodulator {
+    mark: Correlator,
+    space: Correlator,
+    window: usize,
+    pos: usize,
+
+    // Line level (true = space) and clock recovery
+    level: bool,
+    pll: i32,
+    pll_step: i32,
+    last_sampled: bool,
+
+    // Data carrier detect
+    dcd_score: i32,
+    dcd: bool,
+    quiet_bits: u32,
+
+    hdlc: ax25::Deframer,
+    il2p: il2p::Deframer,
+}
+
+impl Demodulator {
+    pub fn new(sample_rate: u32, profile: ModemProfile) -> Self {
+        let baud = profile.baud;
+        let window = ((sample_rate + baud / 2) / baud).clamp(1, MAX_WINDOW as u32) as usize;
+
+        Self {
+            mark: Correlator::new(profile.mark_hz, sample_rate),
+            space: Correlator::new(profile.space_hz, sample_rate),
+            window,
+            pos: 0,
+            level: false,
+            pll: 0,
+            pll_step: (((baud as u64) << 32) / sample_rate as u64) as i32,
+            last_sampled: false,
+            dcd_score: 0,
+            dcd: false,
+            quiet_bits: 0,
+            hdlc: ax25::Deframer::new(),
+            il2p: il2p::Deframer::new(),
+        }
+    }
+
+    /// Feed one sample. Returns a frame (with FCS) when one has been
+    /// received intact.
+    pub fn push_sample(&mut self, x: i16) -> Option<Vec<u8, MAX_FRAME_LEN>> {
+        let mark = self.mark.push(self.pos, x);
+        let space = self.space.push(self.pos, x);
+        self.pos += 1;
+        if self.pos == self.window {
+            self.pos = 0;
+        }
+
+        let level = space > mark;
+        if level != self.level {
+            self.update_dcd(self.pll.unsigned_abs() < DCD_WINDOW);
+            self.quiet_bits = 0;
+
+            // Transitions should land at phase zero; pull the clock there
+            self.pll = ((self.pll as i64 * PLL_INERTIA_Q8) >> 8) as i32;
+            self.level = level;
+        }
+
+        // Sample mid-bit, when the PLL phase wraps
+        let prev = self.pll;
+        self.pll = self.pll.wrapping_add(self.pll_step);
+        if prev < 0 || self.pll >= 0 {
+            return None;
+        }
+
+        self.sample_bit(level)
+    }
+
+    /// Whether a packet signal is on the channel.
+    pub fn dcd(&self) -> bool {
+        self.dcd
+    }
+
+    fn update_dcd(&mut self, on_time: bool) {
+        let delta = if on_time { 1 } else { -1 };
+        self.dcd_score = (self.dcd_score + delta).clamp(0, DCD_MAX);
+
+        if self.dcd_score >= DCD_ON {
+            self.dcd = true;
+        } else if self.dcd_score < DCD_OFF {
+            self.dcd = false;
+        }
+    }
+
+    fn sample_bit(&mut self, level: bool) -> Option<Vec<u8, MAX_FRAME_LEN>> {
+        self.quiet_bits += 1;
+        if self.quiet_bits > DCD_QUIET_BITS {
+            self.update_dcd(false);
+        }
+
+        // NRZI: no change is a 1
+        let bit = level == self.last_sampled;
+        self.last_sampled = level;
+
+        // IL2P is sent as levels, 1 => MARK
+        let il2p = self.il2p.push_bit(!level);
+        let hdlc = self.hdlc.push_bit(bit);
+        hdlc.or(il2p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ax25::{AddressField, Framing};
+    use crate::config::ModemConfig;
+    use crate::hardware::audio::{Sample, SAMPLE_RATE};
+    use crate::modem::{AfskModulator, Modulator};
+    use crate::onair::TxFrame;
+
+    fn frame(info: &[u8]) -> Vec<u8, MAX_FRAME_LEN> {
+        let dest = AddressField::from_text("APZ", 0).unwrap();
+        let src = AddressField::from_text("N0CALL", 7).unwrap();
+        let digis = [AddressField::from_text("WIDE1", 1).unwrap()];
+        ax25::build_ui_frame(dest, src, &digis, info).unwrap()
+    }
+
+    /// Runs a frame through our own modulator and back through the
+    /// demodulator, with `map` applied to every sample in between.
+    fn loopback(framing: Framing, frame: &[u8], map: impl Fn(i16) -> i16) -> Option<Vec<u8, MAX_FRAME_LEN>> {
+        loopback_at(ModemProfile::BELL_202, framing, frame, map)
+    }
+
+    fn loopback_at(
+        profile: ModemProfile,
+        framing: Framing,
+        frame: &[u8],
+        map: impl Fn(i16) -> i16,
+    ) -> Option<Vec<u8, MAX_FRAME_LEN>> {
+        let tx = TxFrame::new(framing, Vec::from_slice(frame).unwrap()).unwrap();
+        let mut modem = AfskModulator::new(SAMPLE_RATE);
+        modem.configure(&ModemConfig { profile, ..ModemConfig::default() });
+        modem.start(tx.into_bits(30, 3));
+
+        let mut demod = Demodulator::new(SAMPLE_RATE, profile);
+        let mut buf = [0 as Sample; 256];
+        let mut idle = 0;
+        let mut received = None;
+
+        // Run until the frame has ended plus a little idle tone
+        while idle < 4 {
+            if !modem.fill_samples(&mut buf) {
+                idle += 1;
+            }
+            for &s in buf.iter() {
+                if let Some(f) = demod.push_sample(map(s as i16)) {
+                    received = Some(f);
+                }
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn decodes_own_ax25_output() {
+        let frame = frame(b"!4903.50N/07201.75WbPHG0020Test 001234");
+        assert_eq!(loopback(Framing::Ax25, &frame, |s| s), Some(frame));
+    }
+
+    #[test]
+    fn decodes_own_il2p_output() {
+        let frame = frame(b">IL2P status text");
+        assert_eq!(loopback(Framing::Il2p, &frame, |s| s), Some(frame));
+    }
+
+    #[test]
+    fn decodes_own_hf_output() {
+        let frame = frame(b"!4903.50N/07201.75W-HF");
+        assert_eq!(loopback_at(ModemProfile::HF_300, Framing::Ax25, &frame, |s| s), Some(frame));
+    }
+
+    #[test]
+    fn decodes_quiet_inverted_signal() {
+        let frame = frame(&[b'~'; 200]);
+        assert_eq!(loopback(Framing::Ax25, &frame, |s| -(s / 16)), Some(frame));
+    }
+
+    #[test]
+    fn carrier_detect_follows_signal() {
+        let tx = TxFrame::new(Framing::Ax25, frame(&[b'x'; 100])).unwrap();
+        let mut modem = AfskModulator::new(SAMPLE_RATE);
+        modem.start(tx.into_bits(30, 3));
+        let mut demod = Demodulator::new(SAMPLE_RATE, ModemProfile::BELL_202);
+
+        // Noise alone never looks like a signal
+        let mut seed: u32 = 1;
+        for _ in 0..SAMPLE_RATE {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            demod.push_sample(seed as i16 / 4);
+            assert!(!demod.dcd());
+        }
+
+        // A frame does, well within the preamble
+        let mut buf = [0 as Sample; 256];
+        let (mut samples, mut detected) = (0, 0);
+        while modem.fill_samples(&mut buf) {
+            for &s in buf.iter() {
+                demod.push_sample(s as i16);
+                samples += 1;
+                detected += demod.dcd() as u32;
+            }
+        }
+        assert!(detected > samples * 3 / 4, "{} of {}", detected, samples);
+
+        // And it lets go soon after the channel goes quiet
+        for _ in 0..SAMPLE_RATE / 10 {
+            demod.push_sample(0);
+        }
+        assert!(!demod.dcd());
+    }
+}
@@ -0,0 +1,159 @@
+//! Whether a GPS fix is good enough to beacon.
+//!
+//! A fix has to be valid and recent, from enough satellites, with low
+//! enough dilution of precision, and 3D if asked for. Figures the GPS
+//! doesn't report (some modules send no GSA) aren't held against it.
+
+use nmea::Nmea;
+
+use crate::config::FixQualityConfig;
+
+/// What the GPS says about its fix.
+pub struct FixQuality {
+    pub valid: bool,
+    /// Seconds since the last good position came in.
+    pub age_s: Option<u32>,
+    pub satellites: Option<u32>,
+    pub hdop: Option<f32>,
+    pub pdop: Option<f32>,
+    /// From the last GSA sentence.
+    pub fix_3d: Option<bool>,
+}
+
+impl FixQuality {
+    pub fn from_nmea(nmea: &Nmea, age_s: Option<u32>, fix_3d: Option<bool>) -> Self {
+        Self {
+            valid: nmea.fix_type.is_some_and(|f| f.is_valid())
+                && nmea.latitude.is_some()
+                && nmea.longitude.is_some(),
+            age_s,
+            satellites: nmea.num_of_fix_satellites,
+            hdop: nmea.hdop,
+            pdop: nmea.pdop,
+            fix_3d,
+        }
+    }
+}
+
+/// Why a fix isn't good enough.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Shortfall {
+    NoFix,
+    TooOld,
+    FewSatellites,
+    HighHdop,
+    HighPdop,
+    Only2d,
+}
+
+impl Shortfall {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NoFix => "no fix",
+            Self::TooOld => "fix too old",
+            Self::FewSatellites => "too few satellites",
+            Self::HighHdop => "HDOP too high",
+            Self::HighPdop => "PDOP too high",
+            Self::Only2d => "2D fix only",
+        }
+    }
+}
+
+/// Checks `q` against the limits in `cfg`. A limit of 0 is no limit.
+pub fn check(cfg: &FixQualityConfig, q: &FixQuality) -> Result<(), Shortfall> {
+    // A DOP over a limit in tenths
+    let over = |dop: Option<f32>, limit_x10: u16| {
+        limit_x10 != 0 && dop.is_some_and(|d| d * 10.0 > limit_x10 as f32)
+    };
+
+    if !q.valid {
+        Err(Shortfall::NoFix)
+    } else if q.satellites.is_some_and(|n| n < cfg.min_satellites as u32) {
+        Err(Shortfall::FewSatellites)
+    } else if over(q.hdop, cfg.max_hdop_x10) {
+        Err(Shortfall::HighHdop)
+    } else if over(q.pdop, cfg.max_pdop_x10) {
+        Err(Shortfall::HighPdop)
+    } else if cfg.require_3d && q.fix_3d == Some(false) {
+        Err(Shortfall::Only2d)
+    } else if q.age_s.is_none_or(|age| age > cfg.max_age_s) {
+        // Checked last: a fix that fails the rest is never taken, so ages
+        Err(Shortfall::TooOld)
+    } else {
+        Ok(())
+    }
+}
+
+/// Wait before retry `attempt` (counting from 1): `retry_ms`, doubling each
+/// time.
+pub fn backoff_ms(cfg: &FixQualityConfig, attempt: u8) -> u32 {
+    cfg.retry_ms.saturating_mul(1 << attempt.saturating_sub(1).min(16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FixFallback;
+
+    fn good() -> FixQuality {
+        FixQuality {
+            valid: true,
+            age_s: Some(1),
+            satellites: Some(7),
+            hdop: Some(1.2),
+            pdop: Some(2.0),
+            fix_3d: Some(true),
+        }
+    }
+
+    fn cfg() -> FixQualityConfig {
+        FixQualityConfig {
+            max_age_s: 5,
+            min_satellites: 4,
+            max_hdop_x10: 50,
+            max_pdop_x10: 60,
+            require_3d: true,
+            attempts: 3,
+            retry_ms: 2_000,
+            fallback: FixFallback::SendStale,
+        }
+    }
+
+    #[test]
+    fn each_limit_is_checked() {
+        let cfg = cfg();
+        assert_eq!(check(&cfg, &good()), Ok(()));
+        assert_eq!(check(&cfg, &FixQuality { valid: false, ..good() }), Err(Shortfall::NoFix));
+        assert_eq!(check(&cfg, &FixQuality { age_s: Some(6), ..good() }), Err(Shortfall::TooOld));
+        assert_eq!(check(&cfg, &FixQuality { age_s: None, ..good() }), Err(Shortfall::TooOld));
+        assert_eq!(check(&cfg, &FixQuality { satellites: Some(3), ..good() }), Err(Shortfall::FewSatellites));
+        assert_eq!(check(&cfg, &FixQuality { hdop: Some(5.1), ..good() }), Err(Shortfall::HighHdop));
+        assert_eq!(check(&cfg, &FixQuality { hdop: Some(5.0), ..good() }), Ok(()));
+        assert_eq!(check(&cfg, &FixQuality { pdop: Some(9.9), ..good() }), Err(Shortfall::HighPdop));
+        assert_eq!(check(&cfg, &FixQuality { fix_3d: Some(false), ..good() }), Err(Shortfall::Only2d));
+    }
+
+    #[test]
+    fn unreported_figures_and_zero_limits_pass() {
+        let q = FixQuality {
+            satellites: None,
+            hdop: None,
+            pdop: None,
+            fix_3d: None,
+            ..good()
+        };
+        assert_eq!(check(&cfg(), &q), Ok(()));
+
+        let cfg = FixQualityConfig { max_hdop_x10: 0, require_3d: false, ..cfg() };
+        assert_eq!(check(&cfg, &FixQuality { hdop: Some(99.0), fix_3d: Some(false), ..good() }), Ok(()));
+    }
+
+    #[test]
+    fn backoff_doubles() {
+        let cfg = cfg();
+        assert_eq!(backoff_ms(&cfg, 1), 2_000);
+        assert_eq!(backoff_ms(&cfg, 2), 4_000);
+        assert_eq!(backoff_ms(&cfg, 3), 8_000);
+        assert_eq!(backoff_ms(&cfg, 255), 2_000 << 16);
+    }
+}
@@ -0,0 +1,286 @@
+//! G3RUH-compatible 9600 baud FSK modulator.
+//!
+//! Baseband audio for a radio's data port rather than tones: the on-air bits
+//! are NRZI coded, whitened by the self-synchronizing 1 + x^12 + x^17
+//! scrambler, and each line bit is sent as a raised cosine pulse spanning
+//! four bits to keep the signal inside an FM channel. Needs an audio rate of
+//! at least four samples per bit.
+//!
+//! Transmit only. Receive still runs the AFSK demodulator, which can't hear
+//! 9600 baud, so DCD never sees the channel busy with it: channel access
+//! comes down to p-persistence alone, and the unit can't hear itself.
+
+use crate::config::{CwIdConfig, ModemConfig};
+use crate::hardware::audio;
+use crate::modem::{CalState, Calibration, CwState, Modulator};
+use crate::onair::OnAirBits;
+
+include!(concat!(env!("OUT_DIR"), "/g3ruh_pulse.rs")); // Imports PULSE_SPAN, PULSE_PHASES, G3RUH_PULSE
+
+pub const BAUD: u32 = 9600;
+
+/// Scrambles one bit. `state` holds the previous outputs, newest in bit 0.
+#[inline]
+pub fn scramble(state: &mut u32, bit: bool) -> bool {
+    let out = bit ^ (*state >> 11 & 1 != 0) ^ (*state >> 16 & 1 != 0);
+    *state = (*state << 1 | out as u32) & 0x1_FFFF;
+    out
+}
+
+pub struct G3ruhModulator {
+    sample_rate: u32,
+    // Bit clock, counts up by BAUD per sample and wraps at `sample_rate`
+    bit_accum: u32,
+    // Last PULSE_SPAN line bits, newest in bit 0
+    history: u32,
+    nrzi_level: bool,
+    scrambler: u32,
+    gain: i32,
+
+    // current source: a frame, a calibration pattern, or a CW ID
+    src: Option<OnAirBits>,
+    cal: Option<CalState>,
+    cw: Option<CwState>,
+}
+
+impl G3ruhModulator {
+    pub fn new(sample_rate: u32) -> Self {
+        assert!(sample_rate >= 4 * BAUD, "G3RUH needs 4 samples per bit");
+
+        let mut this = Self {
+            sample_rate,
+            bit_accum: 0,
+            history: 0,
+            nrzi_level: false,
+            scrambler: 0,
+            gain: 0,
+            src: None,
+            cal: None,
+            cw: None,
+        };
+        this.configure(&ModemConfig::default());
+        this
+    }
+
+    /// The next line bit. Calibration patterns skip the NRZI and scrambler so
+    /// alternating bits come out as a steady 4800 Hz tone.
+    fn next_line_bit(&mut self, tx_active: &mut bool) -> bool {
+        let last = self.history & 1 != 0;
+        if let Some(cal) = self.cal.as_mut() {
+            if let Some(space) = cal.next(!last) {
+                return !space;
+            }
+            self.cal = None;
+            *tx_active = false;
+        }
+
+        // Once the frame is over, idle on 1s like the AFSK modem's MARK
+        let bit = match self.src.as_mut().map(|src| src.pull_bit()) {
+            Some(Some(bit)) => bit,
+            Some(None) => {
+                self.src = None;
+                *tx_active = false;
+                true
+            }
+            None => true,
+        };
+
+        if !bit { self.nrzi_level = !self.nrzi_level; } // 0 => toggle
+        scramble(&mut self.scrambler, self.nrzi_level)
+    }
+}
+
+impl Modulator for G3ruhModulator {
+    /// Only the level applies; G3RUH has its own baud rate and no tones.
+    fn configure(&mut self, cfg: &ModemConfig) {
+        self.gain = cfg.tx_level.min(i16::MAX as u16) as i32;
+    }
+
+    fn baud(&self) -> u32 {
+        BAUD
+    }
+
+    fn start(&mut self, bits: OnAirBits) {
+        self.src = Some(bits);
+    }
+
+    fn calibrate(&mut self, cal: Calibration, cfg: &ModemConfig) {
+        self.configure(cfg);
+        self.cal = Some(CalState::new(cal, BAUD));
+    }
+
+    fn identify(&mut self, call: &'static str, cfg: &CwIdConfig, modem: &ModemConfig) {
+        self.cw = Some(CwState::new(call, cfg, modem, self.sample_rate));
+    }
+
+    fn is_sending(&self) -> bool {
+        self.src.is_some() || self.cal.is_some() || self.cw.is_some()
+    }
+
+    fn abort(&mut self) {
+        self.src = None;
+        self.cal = None;
+        self.cw = None;
+    }
+
+    fn fill_samples(&mut self, buf: &mut [audio::Sample]) -> bool {
+        if let Some(cw) = self.cw.as_mut() {
+            let still_tx = cw.fill_samples(buf);
+            if !still_tx {
+                self.cw = None;
+            }
+            return still_tx;
+        }
+
+        let mut tx_active = self.is_sending();
+        for s in buf.iter_mut() {
+            self.bit_accum += BAUD;
+            if self.bit_accum >= self.sample_rate {
+                self.bit_accum -= self.sample_rate;
+                let line = self.next_line_bit(&mut tx_active);
+                self.history = self.history << 1 | line as u32;
+            }
+
+            // Sum the pulses of the bits in the filter span. The output runs
+            // half a span behind the newest bit.
+            let phase = (self.bit_accum * PULSE_PHASES as u32 / self.sample_rate) as usize;
+            let mut acc = 0i32;
+            for k in 0..PULSE_SPAN {
+                let tap = G3RUH_PULSE[k * PULSE_PHASES + phase] as i32;
+                acc += if self.history >> k & 1 != 0 { tap } else { -tap };
+            }
+
+            *s = audio::stereo(((acc * self.gain) >> 15) as i16);
+        }
+
+        tx_active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ax25::{self, AddressField, Framing};
+    use crate::modem::CalTone;
+    use crate::onair::TxFrame;
+
+    const RATE: u32 = 48_000;
+    const SAMPLES_PER_BIT: usize = (RATE / BAUD) as usize;
+
+    /// The scrambler written out as the textbook recurrence, over a plain
+    /// history of outputs.
+    fn reference_scramble(input: &[bool]) -> [bool; 64] {
+        let mut out = [false; 64];
+        for n in 0..input.len() {
+            let tap = |d: usize| n >= d && out[n - d];
+            out[n] = input[n] ^ tap(12) ^ tap(17);
+        }
+        out
+    }
+
+    #[test]
+    fn scrambler_matches_polynomial() {
+        let mut input = [false; 64];
+        let mut x: u32 = 0xACE1;
+        for b in input.iter_mut() {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            *b = x & 1 != 0;
+        }
+
+        let mut state = 0;
+        let out: [bool; 64] = core::array::from_fn(|n| scramble(&mut state, input[n]));
+        assert_eq!(out, reference_scramble(&input));
+
+        // An impulse echoes at both taps and their combinations
+        let mut impulse = [false; 64];
+        impulse[0] = true;
+        let expected = reference_scramble(&impulse);
+        let mut state = 0;
+        for (n, &b) in impulse.iter().enumerate() {
+            assert_eq!(scramble(&mut state, b), expected[n], "bit {}", n);
+        }
+        assert!(expected[12] && expected[17] && expected[24]);
+    }
+
+    /// Index of the first bit centre. The first bit is clocked in on the
+    /// last sample of its period, and its pulse peaks half the filter span
+    /// later.
+    const FIRST_CENTRE: usize = SAMPLES_PER_BIT - 1 + PULSE_SPAN / 2 * SAMPLES_PER_BIT;
+
+    /// Runs the modulator until it has been idle for a couple of buffers,
+    /// handing `f` the sample at each bit centre.
+    fn run(modem: &mut G3ruhModulator, mut f: impl FnMut(i16)) {
+        let mut buf = [0 as audio::Sample; 96];
+        let (mut n, mut idle) = (0, 0);
+        while idle < 2 {
+            if !modem.fill_samples(&mut buf) {
+                idle += 1;
+            }
+            for &s in buf.iter() {
+                if n >= FIRST_CENTRE && (n - FIRST_CENTRE).is_multiple_of(SAMPLES_PER_BIT) {
+                    f(s as i16);
+                }
+                n += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn waveform_carries_frame() {
+        let dest = AddressField::from_text("APZ", 0).unwrap();
+        let src = AddressField::from_text("N0CALL", 7).unwrap();
+        let frame = ax25::build_ui_frame(dest, src, &[], b"!4903.50N/07201.75W>9600 baud").unwrap();
+
+        let mut modem = G3ruhModulator::new(RATE);
+        let tx = TxFrame::new(Framing::Ax25, frame.clone()).unwrap();
+        modem.start(tx.into_bits(8, 2));
+
+        // Take the waveform apart the way a receiver would: slice at bit
+        // centres, descramble, undo NRZI, and look for frames
+        let mut deframer = ax25::Deframer::new();
+        let (mut history, mut last) = (0u32, false);
+        let mut received = None;
+
+        // Raised cosine pulses don't interfere at bit centres, so every
+        // centre sits at full level
+        let peak = G3RUH_PULSE[PULSE_SPAN / 2 * PULSE_PHASES] as i32;
+        run(&mut modem, |s| {
+            assert!((s as i32).abs().abs_diff(peak) <= 2, "{} vs {}", s, peak);
+
+            let line = s > 0;
+            let tap = |d: u32| history >> (d - 1) & 1 != 0;
+            let level = line ^ tap(12) ^ tap(17);
+            history = history << 1 | line as u32;
+
+            let bit = level == last;
+            last = level;
+            if let Some(f) = deframer.push_bit(bit) {
+                received = Some(f);
+            }
+        });
+
+        assert_eq!(received, Some(frame));
+    }
+
+    #[test]
+    fn alternating_calibration_is_half_baud_tone() {
+        let mut modem = G3ruhModulator::new(RATE);
+        let cal = Calibration { tone: CalTone::Alternating, duration_ms: 10 };
+        modem.calibrate(cal, &ModemConfig::default());
+
+        // Once the filter has filled, the sign flips every bit
+        let bits = (10 * BAUD / 1000) as usize - PULSE_SPAN;
+        let (mut n, mut last) = (0, None);
+        run(&mut modem, |s| {
+            let positive = s > 0;
+            if n < bits && let Some(prev) = last {
+                assert_ne!(positive, prev, "bit {}", n);
+            }
+            last = Some(positive);
+            n += 1;
+        });
+        assert!(n > bits);
+    }
+}
@@ -0,0 +1,120 @@
+//! Geofences: whether a position is inside a zone from the config.
+//!
+//! All in whole microdegrees and metres, no floats. Distances treat the
+//! earth as flat over the size of a zone, which is near enough for a few
+//! kilometres and doesn't matter much for more.
+
+use crate::aprs::UDEG_PER_KM;
+use crate::config::{Shape, Zone};
+
+/// The first of `zones` that contains `latitude`, `longitude`.
+pub fn find(zones: &[Zone], latitude: i32, longitude: i32) -> Option<&Zone> {
+    zones.iter().find(|z| contains(&z.shape, latitude, longitude))
+}
+
+/// Whether `shape` contains the point. Points on a polygon's edge may fall
+/// either way.
+pub fn contains(shape: &Shape, latitude: i32, longitude: i32) -> bool {
+    match shape {
+        Shape::Circle { latitude: lat, longitude: lon, radius_m } => {
+            // Degrees of longitude shrink away from the equator
+            let dy = (latitude as i64 - *lat as i64) * 1_000 / UDEG_PER_KM;
+            let dx = (longitude as i64 - *lon as i64) * cos_x10k(*lat) / 10_000 * 1_000 / UDEG_PER_KM;
+            (dx * dx + dy * dy) as u64 <= *radius_m as u64 * *radius_m as u64
+        }
+        Shape::Polygon(corners) => {
+            // Count the edges crossed going east from the point: odd is inside
+            let (py, px) = (latitude as i64, longitude as i64);
+            let mut inside = false;
+            let mut prev = match corners.last() {
+                Some(&c) => c,
+                None => return false,
+            };
+            for &corner in corners.iter() {
+                let (ay, ax) = (prev.0 as i64, prev.1 as i64);
+                let (by, bx) = (corner.0 as i64, corner.1 as i64);
+                if (ay > py) != (by > py) {
+                    // Whether the point is west of where the edge crosses its
+                    // latitude, without dividing: flip for edges going south
+                    let west = (px - ax) * (by - ay) < (bx - ax) * (py - ay);
+                    if west == (by > ay) {
+                        inside = !inside;
+                    }
+                }
+                prev = corner;
+            }
+            inside
+        }
+    }
+}
+
+/// Cosine of a latitude in microdegrees, times 10 000. Bhaskara's
+/// approximation, good to a tenth of a percent or so.
+fn cos_x10k(latitude: i32) -> i64 {
+    // Millidegrees, and 180° squared in them
+    let x = (latitude as i64 / 1_000).clamp(-90_000, 90_000);
+    const HALF_TURN_SQ: i64 = 180_000 * 180_000;
+    (HALF_TURN_SQ - 4 * x * x) * 10_000 / (HALF_TURN_SQ + x * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ZoneAction;
+
+    const HOME: (i32, i32) = (49_058_333, -72_029_166);
+
+    fn polygon(corners: &[(i32, i32)]) -> Shape {
+        Shape::Polygon(heapless::Vec::from_slice(corners).unwrap())
+    }
+
+    #[test]
+    fn circles_allow_for_latitude() {
+        let circle = Shape::Circle { latitude: HOME.0, longitude: HOME.1, radius_m: 500 };
+        assert!(contains(&circle, HOME.0, HOME.1));
+        // 450 m north, then 550
+        assert!(contains(&circle, HOME.0 + 4_054, HOME.1));
+        assert!(!contains(&circle, HOME.0 + 4_955, HOME.1));
+        // At 49°N a degree of longitude is about 73 km: 450 m east, then 550
+        assert!(contains(&circle, HOME.0, HOME.1 + 6_150));
+        assert!(!contains(&circle, HOME.0, HOME.1 + 7_520));
+
+        assert_eq!(cos_x10k(0), 10_000);
+        assert_eq!(cos_x10k(90_000_000), 0);
+        // cos 49.058° is 0.6552
+        assert!((6_540..=6_560).contains(&cos_x10k(HOME.0)));
+    }
+
+    #[test]
+    fn polygons_either_way_round() {
+        // An L shape, missing its north-east corner
+        let l = [(0, 0), (0, 2_000), (1_000, 2_000), (1_000, 1_000), (2_000, 1_000), (2_000, 0)];
+        let mut reversed = l;
+        reversed.reverse();
+        for shape in [polygon(&l), polygon(&reversed)] {
+            assert!(contains(&shape, 500, 500));
+            assert!(contains(&shape, 500, 1_500));
+            assert!(contains(&shape, 1_500, 500));
+            assert!(!contains(&shape, 1_500, 1_500));
+            assert!(!contains(&shape, -500, 500));
+            assert!(!contains(&shape, 500, 2_500));
+        }
+        assert!(!contains(&polygon(&[]), 0, 0));
+    }
+
+    #[test]
+    fn first_matching_zone_applies() {
+        let zone = |name: &str, radius_m, action| Zone {
+            name: heapless::String::try_from(name).unwrap(),
+            shape: Shape::Circle { latitude: HOME.0, longitude: HOME.1, radius_m },
+            action,
+        };
+        let zones = [
+            zone("home", 200, ZoneAction::Quiet),
+            zone("town", 5_000, ZoneAction::Symbol { table: '/', code: '>' }),
+        ];
+        assert_eq!(find(&zones, HOME.0, HOME.1).unwrap().name, "home");
+        assert_eq!(find(&zones, HOME.0 + 9_009, HOME.1).unwrap().name, "town");
+        assert!(find(&zones, HOME.0 + 90_090, HOME.1).is_none());
+    }
+}
@@ -1,13 +1,20 @@
 use defmt::println;
+use nmea::{ParseResult, SentenceType};
+use nmea::sentences::gsa::{GsaData, GsaMode2};
 
 use crate::app::Shared;
+use crate::fixquality::{self, FixQuality};
+use crate::gpstime;
+use crate::hardware::{audio, flash};
 use crate::hardware::uart::use_queue;
+use crate::lastfix::{self, LastFix};
 use crate::sched::Tickable;
 use crate::co::UART_BUFFER_SIZE;
 
 
 pub struct GpsTask {
     next_run_at: u64,
+    next_save_at: u64,
     line_buf: heapless::String<UART_BUFFER_SIZE>,
 }
 
@@ -15,6 +22,7 @@ impl GpsTask {
     pub fn new() -> Self {
         Self {
             next_run_at: 0,
+            next_save_at: 0,
             line_buf: heapless::String::new(),
         }
     }
@@ -22,6 +30,8 @@ impl GpsTask {
     fn run(&mut self, now: u64, shared: &mut Shared) {
         self.next_run_at = now + 900; // 1 second + a little more
 
+        let mut position = false;
+        let mut rmc = false;
         use_queue(|mut q| {
             //println!("Queue: {}", q.len());
             while let Some(b) = q.dequeue() {
@@ -29,8 +39,30 @@ impl GpsTask {
                     // Full line acquired
                     //let head = &self.line_buf[..10]; // TODO: Can index out of bounds
 
-                    if let Err(_) = shared.nmea.parse(self.line_buf.as_str()) {
-                        //println!("Error with: \"{}\"", head);
+                    // `Nmea` doesn't keep whether the fix is 2D or 3D, so GSA
+                    // is taken in here instead
+                    let parsed = if self.line_buf.get(3..6) == Some("GSA") {
+                        nmea::parse_str(self.line_buf.as_str()).map(|result| {
+                            if let ParseResult::GSA(gsa) = result {
+                                merge_gsa(shared, gsa);
+                            }
+                            SentenceType::GSA
+                        })
+                    } else {
+                        shared.nmea.parse(self.line_buf.as_str())
+                    };
+                    match parsed {
+                        Ok(SentenceType::RMC) => {
+                            position = true;
+                            rmc = true;
+                        }
+                        Ok(SentenceType::GGA | SentenceType::GNS | SentenceType::GLL) => {
+                            position = true;
+                        }
+                        Ok(_) => {}
+                        Err(_) => {
+                            //println!("Error with: \"{}\"", head);
+                        }
                     }
 
                     self.line_buf.clear();
@@ -39,9 +71,50 @@ impl GpsTask {
                 }
             }
         });
+
+        // RMC has the date as well as the time, so it sets the clock
+        if rmc && let Some(utc) = gpstime::nmea_utc_ms(&shared.nmea) {
+            shared.clock.sample(now, utc);
+        }
+
+        // Only a fresh position that passes muster counts as a good fix
+        if position
+            && let Some(fix) = LastFix::from_nmea(&shared.nmea)
+            && fixquality::check(
+                &shared.config.fix_quality,
+                &FixQuality::from_nmea(&shared.nmea, Some(0), shared.fix_3d),
+            ).is_ok()
+        {
+            shared.last_fix.update(now, fix);
+        }
+
+        // Save a changed position now and then. Writing flash stalls the
+        // audio interrupts, so never while keyed up.
+        if now >= self.next_save_at
+            && !audio::keyed_up()
+            && let Some(fix) = shared.last_fix.unsaved()
+        {
+            let mut page = [0xFF; flash::PAGE_SIZE];
+            page[..lastfix::RECORD_LEN].copy_from_slice(&fix.encode());
+            flash::write(&page);
+            shared.last_fix.mark_saved(fix);
+            println!("Saved last known position");
+
+            self.next_save_at = now + shared.config.last_fix.save_interval_s as u64 * 1_000;
+        }
     }
 }
 
+/// Takes in a GSA sentence as `Nmea::parse` would, along with whether the
+/// fix is 3D.
+fn merge_gsa(shared: &mut Shared, gsa: GsaData) {
+    shared.fix_3d = Some(gsa.mode2 == GsaMode2::Fix3D);
+    shared.nmea.fix_satellites_prns = Some(gsa.fix_sats_prn);
+    shared.nmea.hdop = gsa.hdop;
+    shared.nmea.vdop = gsa.vdop;
+    shared.nmea.pdop = gsa.pdop;
+}
+
 impl Tickable for GpsTask {
     fn next_run_at(&self) -> u64 {
         self.next_run_at
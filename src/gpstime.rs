@@ -0,0 +1,130 @@
+//! GPS-disciplined UTC, mapped onto scheduler milliseconds.
+//!
+//! Each NMEA sentence carries the UTC of its fix, and turns up some time
+//! after it: the receiver takes a while to send it, and `GpsTask` only
+//! reads the UART every second or so. That delay is never negative, so the
+//! sample that arrived soonest after its time is the best guess at the
+//! offset, and later ones only win by being earlier still. The timer and
+//! the GPS don't run at quite the same rate, so the kept offset is let
+//! slide back slowly, for newer samples to pull it forward again.
+
+use chrono::{DateTime, Datelike, Timelike};
+use nmea::Nmea;
+
+use crate::aprs::Timestamp;
+
+/// How fast the kept offset may slide, as one part in this many: 100 ppm,
+/// more than the crystal will drift.
+const SLIDE: u64 = 10_000;
+
+pub struct GpsClock {
+    /// UTC (ms since 1970) less uptime (ms), from the sample taken at the
+    /// uptime alongside.
+    best: Option<(i64, u64)>,
+}
+
+impl GpsClock {
+    pub const fn new() -> Self {
+        Self { best: None }
+    }
+
+    /// The kept offset at uptime `now`, slid along since it was taken.
+    fn offset(&self, now: u64) -> Option<i64> {
+        self.best.map(|(offset, at)| offset - (now.saturating_sub(at) / SLIDE) as i64)
+    }
+
+    /// Takes a sentence read at uptime `now` (ms) that says it's `utc_ms`.
+    pub fn sample(&mut self, now: u64, utc_ms: i64) {
+        let sample = utc_ms - now as i64;
+        if self.offset(now).is_none_or(|kept| sample >= kept) {
+            self.best = Some((sample, now));
+        }
+    }
+
+    /// UTC (ms since 1970) at uptime `now`, once the GPS has given the time.
+    pub fn utc_ms(&self, now: u64) -> Option<i64> {
+        Some(now as i64 + self.offset(now)?)
+    }
+
+    /// Uptime (ms) at `utc_ms`, or 0 if that was before start-up. Uses the
+    /// offset as last sampled.
+    pub fn uptime_at(&self, utc_ms: i64) -> Option<u64> {
+        let (offset, _) = self.best?;
+        Some((utc_ms - offset).max(0) as u64)
+    }
+}
+
+/// UTC of the last fix time and date the GPS sent, in ms since 1970.
+pub fn nmea_utc_ms(nmea: &Nmea) -> Option<i64> {
+    let (date, time) = (nmea.fix_date?, nmea.fix_time?);
+    Some(date.and_time(time).and_utc().timestamp_millis())
+}
+
+/// APRS day/hour/minute timestamp for `utc_s`, seconds since 1970.
+pub fn dhm(utc_s: i64) -> Option<Timestamp> {
+    let t = DateTime::from_timestamp(utc_s, 0)?;
+    Some(Timestamp::Dhm {
+        day: t.day() as u8,
+        hour: t.hour() as u8,
+        minute: t.minute() as u8,
+    })
+}
+
+/// Start of the next slot after `utc_ms`, with slots `offset_s` into every
+/// `interval_s` of UTC.
+pub fn next_slot(utc_ms: i64, interval_s: u32, offset_s: u32) -> i64 {
+    let interval = interval_s.max(1) as i64 * 1_000;
+    let offset = offset_s as i64 * 1_000 % interval;
+    let slot = (utc_ms - offset).div_euclid(interval) * interval + offset;
+    slot + interval
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-03-05 14:07:00 UTC
+    const UTC: i64 = 1_709_647_620_000;
+
+    #[test]
+    fn earliest_sample_wins() {
+        let mut clock = GpsClock::new();
+        assert_eq!(clock.utc_ms(0), None);
+
+        // Read 400 ms late, then 50 ms late
+        clock.sample(10_400, UTC);
+        assert_eq!(clock.utc_ms(10_400), Some(UTC));
+        clock.sample(11_050, UTC + 1_000);
+        assert_eq!(clock.utc_ms(11_050), Some(UTC + 1_000));
+        // Later reads don't move it back
+        clock.sample(12_900, UTC + 2_000);
+        assert_eq!(clock.utc_ms(12_050), Some(UTC + 2_000));
+        assert_eq!(clock.uptime_at(UTC + 2_000), Some(12_050));
+    }
+
+    #[test]
+    fn offset_follows_drift() {
+        // The timer runs 50 ppm slow, then fast, against GPS; reads are
+        // 10 ms late
+        for fast in [false, true] {
+            let uptime = |s: u64| if fast { s * 1_000 + s / 20 } else { s * 1_000 - s / 20 } + 10;
+            let mut clock = GpsClock::new();
+            for s in 0..=3_600u64 {
+                clock.sample(uptime(s), UTC + s as i64 * 1_000);
+            }
+            let error = clock.utc_ms(uptime(3_600)).unwrap() - (UTC + 3_600_000);
+            // No worse than the read delay, give or take the whole-ms steps
+            assert!((-11..=1).contains(&error), "fast {}: {}", fast, error);
+        }
+    }
+
+    #[test]
+    fn slots_are_aligned_to_utc() {
+        // 14:07:00, slots at :20 past each minute
+        assert_eq!(next_slot(UTC, 60, 20), UTC + 20_000);
+        assert_eq!(next_slot(UTC + 19_999, 60, 20), UTC + 20_000);
+        assert_eq!(next_slot(UTC + 20_000, 60, 20), UTC + 80_000);
+        // Ten-minute slots, 3:30 in
+        assert_eq!(next_slot(UTC, 600, 210), UTC + 390_000);
+    }
+}
@@ -0,0 +1,204 @@
+// ADC audio input with double-buffer DMA.
+// Free-runs the ADC on the radio's audio (GP26 / ADC0) at a fixed rate and
+// hands full ping/pong buffers to the receive side, DC offset removed.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use defmt::println;
+
+use rp_pico::hal::{self, Clock};
+use hal::pac;
+
+use pac::interrupt;
+use hal::adc::{Adc, AdcFifo, AdcPin, DmaReadTarget};
+use hal::clocks::ClocksManager;
+use hal::dma::{single_buffer::{self, Transfer}, Channel, SingleChannel, CH1};
+use hal::gpio::{self, Pin};
+
+pub type Sample = u16;
+pub const BUF_LEN: usize = 256;
+
+/// Capture rate. Anything from 8 kHz up works; 9.6 kHz divides the 48 MHz
+/// ADC clock exactly and gives 8 samples per 1200 baud bit.
+pub const SAMPLE_RATE: u32 = 9_600;
+
+/// The DC tracker follows the input over 2^DC_SHIFT samples.
+const DC_SHIFT: u32 = 10;
+
+// Ping-pong sample storage. Handed out once in `AdcIn::new`, after which the
+// two buffers only move between the DMA and the consumer.
+static mut BUF_PING: [Sample; BUF_LEN] = [0; BUF_LEN];
+static mut BUF_PONG: [Sample; BUF_LEN] = [0; BUF_LEN];
+
+type AudioInPin = Pin<gpio::bank0::Gpio26, gpio::FunctionSioInput, gpio::PullNone>;
+type DmaTransfer = Transfer<Channel<CH1>, DmaReadTarget<Sample>, &'static mut [Sample; BUF_LEN]>;
+
+pub struct AdcIn {
+    xfer: Option<DmaTransfer>,
+    fifo: AdcFifo<'static, Sample>,
+    _pin: AdcPin<AudioInPin>,
+
+    // Exactly one of these holds the buffer the DMA isn't writing to
+    filled: Option<&'static mut [Sample; BUF_LEN]>,
+    spare: Option<&'static mut [Sample; BUF_LEN]>,
+
+    overruns: u32,
+    dc_q8: i32,
+    sample_rate: u32,
+}
+
+#[allow(static_mut_refs)]
+impl AdcIn {
+    /// Starts free-running conversion on GP26 at `sample_rate` and the first
+    /// DMA transfer into the ping buffer.
+    pub fn new(
+        mut ch: Channel<CH1>,
+        device: pac::ADC,
+        pin: AudioInPin,
+        resets: &mut pac::RESETS,
+        clocks: &ClocksManager,
+        sample_rate: u32,
+    ) -> Self {
+        assert!(sample_rate >= 8_000, "ADC sample rate below 8 kHz");
+
+        // The FIFO borrows the ADC for as long as it runs, which is forever
+        let adc = cortex_m::singleton!(: Adc = Adc::new(device, resets)).unwrap();
+        let mut pin = AdcPin::new(pin).unwrap();
+
+        // rate = adc_clk / (1 + int + frac / 256)
+        let adc_clk = clocks.adc_clock.freq().to_Hz() as u64;
+        let div_q8 = ((adc_clk << 8) + sample_rate as u64 / 2) / sample_rate as u64 - 256;
+        let sample_rate = ((adc_clk << 8) / (div_q8 + 256)) as u32;
+
+        println!("ADC rate: {} (div {}+{}/256)", sample_rate, div_q8 >> 8, div_q8 & 0xFF);
+
+        let fifo = adc
+            .build_fifo()
+            .clock_divider((div_q8 >> 8) as u16, div_q8 as u8)
+            .set_channel(&mut pin)
+            .enable_dma()
+            .start_paused();
+
+        ch.enable_irq1();
+        let xfer = single_buffer::Config::new(ch, fifo.dma_read_target(), unsafe { &mut BUF_PING }).start();
+
+        let mut this = Self {
+            xfer: Some(xfer),
+            fifo,
+            _pin: pin,
+            filled: None,
+            spare: Some(unsafe { &mut BUF_PONG }),
+            overruns: 0,
+            dc_q8: (1 << 15) << 8,
+            sample_rate,
+        };
+
+        this.fifo.resume();
+        unsafe { pac::NVIC::unmask(pac::interrupt::DMA_IRQ_1) };
+
+        this
+    }
+
+    /// ISR helper - must be called from `DMA_IRQ_1`
+    pub fn on_dma_complete(&mut self) {
+        let Some(xfer) = self.xfer.as_mut() else { return; };
+        if !xfer.check_irq1() {
+            return;
+        }
+        let (ch, from, buf) = self.xfer.take().unwrap().wait();
+
+        // The FIFO filled up before the DMA came back for it
+        if self.fifo.is_over() {
+            self.overruns += 1;
+        }
+
+        // If the consumer never took the last buffer, write over it
+        let next = match self.filled.take() {
+            Some(stale) => {
+                self.overruns += 1;
+                stale
+            }
+            None => self.spare.take().unwrap(),
+        };
+
+        self.xfer = Some(single_buffer::Config::new(ch, from, next).start());
+        self.filled = Some(buf);
+    }
+
+    /// Copies the oldest full buffer into `out` as signed samples around
+    /// zero. Returns `false` if no buffer has filled since the last call.
+    pub fn read_samples(&mut self, out: &mut [i16; BUF_LEN]) -> bool {
+        let Some(buf) = self.filled.take() else { return false; };
+
+        for (o, &s) in out.iter_mut().zip(buf.iter()) {
+            // 12 bits up to 16, then track and remove the bias
+            let x = ((s & 0x0FFF) << 4) as i32;
+            self.dc_q8 += ((x << 8) - self.dc_q8) >> DC_SHIFT;
+            *o = (x - (self.dc_q8 >> 8)).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+
+        self.spare = Some(buf);
+        true
+    }
+
+    /// Buffers lost because nobody read them in time, plus ADC FIFO overflows.
+    pub fn overruns(&self) -> u32 {
+        self.overruns
+    }
+
+    /// The rate actually achieved by the clock divider.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+//------------------------------------------------------------------------------
+// Global singleton for DMA_IRQ_1:
+// ADC_IN - `self`
+
+pub(crate) static ADC_IN: Mutex<RefCell<Option<AdcIn>>> = Mutex::new(RefCell::new(None));
+
+#[pac::interrupt]
+fn DMA_IRQ_1() {
+    critical_section::with(|cs| {
+        if let Some(ref mut adc) = *ADC_IN.borrow(cs).borrow_mut() {
+            adc.on_dma_complete();
+        }
+    });
+}
+
+// Public facade for accessing the singleton -----------------------------------
+
+pub fn read_samples(out: &mut [i16; BUF_LEN]) -> bool {
+    critical_section::with(|cs| {
+        ADC_IN
+            .borrow(cs)
+            .borrow_mut()
+            .as_mut()
+            .map(|a| a.read_samples(out))
+            .unwrap_or(false)
+    })
+}
+
+pub fn overruns() -> u32 {
+    critical_section::with(|cs| {
+        ADC_IN
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map(|a| a.overruns())
+            .unwrap_or(0)
+    })
+}
+
+pub fn sample_rate() -> u32 {
+    critical_section::with(|cs| {
+        ADC_IN
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map(|a| a.sample_rate())
+            .unwrap_or(SAMPLE_RATE)
+    })
+}
@@ -1,8 +1,6 @@
-// I2S audio output with double-buffer DMA.
+// I2S audio output with a DMA-fed ring of buffers.
 // Produces a constant stream (silence by default) and lets higher layers
-// push AFSK samples zero-copy into the ping/pong buffer.
-
-use core::sync::atomic::{AtomicBool, Ordering};
+// push AFSK samples zero-copy into the ring, a few buffers ahead of the DMA.
 
 use defmt::println;
 
@@ -14,38 +12,135 @@ use hal::pac;
 use pac::PIO0;
 use pac::interrupt;
 use hal::clocks::ClocksManager;
-use hal::dma::{single_buffer::{self, Transfer}, Channel, Channels, SingleChannel, CH0};
+use hal::dma::{single_buffer::{self, Transfer}, Channel, SingleChannel, CH0};
 use hal::pio::{PIO, PIOBuilder, ShiftDirection, Tx, SM0, PinDir};
 
 use super::ptt::PttWrapper;
 
 pub type Sample = u32;
 pub const BUF_LEN: usize = 256;
-pub const SAMPLE_RATE: u32 = 8_000;
 
-// Ping-pong sample storage
-pub static mut BUF_PING: [Sample; BUF_LEN] = [0; BUF_LEN];
-pub static mut BUF_PONG: [Sample; BUF_LEN] = [0; BUF_LEN];
-static ZERO_BUF:         [Sample; BUF_LEN] = [0; BUF_LEN];
+/// Output rates the I2S path can be asked for.
+pub const RATES: [u32; 6] = [8_000, 9_600, 16_000, 22_050, 44_100, 48_000];
+
+/// Output rate, one of `RATES`. 9.6 kHz gives a whole 8 samples per 1200
+/// baud bit, the same as the ADC.
+#[cfg(not(feature = "g3ruh"))]
+pub const SAMPLE_RATE: u32 = 9_600;
+/// G3RUH pulse shaping needs several samples per 9600 baud bit.
+#[cfg(feature = "g3ruh")]
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// PIO cycles per stereo frame: 16 bits * 2 (stereo) * 2 (both edges)
+const PIO_CYCLES_PER_SAMPLE: u32 = 16 * 2 * 2;
+
+/// Picks the 16.8 PIO clock divisor giving the rate closest to
+/// `sample_rate`. Returns the divisor and the rate it achieves.
+fn pio_divisor(sys_clk: u32, sample_rate: u32) -> (u32, u32) {
+    let sys_q8 = (sys_clk as u64) << 8;
+    let cycles = (sample_rate * PIO_CYCLES_PER_SAMPLE) as u64;
+    let achieved = |div_q8: u64| {
+        let per_sample = div_q8 * PIO_CYCLES_PER_SAMPLE as u64;
+        ((sys_q8 + per_sample / 2) / per_sample) as u32
+    };
+
+    // The rate falls as the divisor rises, so the best is one side or the
+    // other of the exact value
+    let lo = (sys_q8 / cycles).clamp(1 << 8, 0xFF_FFFF);
+    let hi = (lo + 1).min(0xFF_FFFF);
+    let div_q8 = if achieved(lo).abs_diff(sample_rate) <= achieved(hi).abs_diff(sample_rate) { lo } else { hi };
+
+    (div_q8 as u32, achieved(div_q8))
+}
+
+const fn ms_to_samples(ms: u32, sample_rate: u32) -> u32 {
+    (ms as u64 * sample_rate as u64 / 1000) as u32
+}
+
+/// Packs one signed sample into both I2S channels.
+#[inline]
+pub const fn stereo(x: i16) -> Sample {
+    let x = x as u16 as u32;
+    (x << 16) | x
+}
+
+/// Longest PTT may stay keyed before it is forced off, until told otherwise
+/// by `set_tx_timeout`.
+pub const TX_TIMEOUT_MS: u32 = 60_000;
+/// How long transmit stays locked out after a timeout.
+pub const TX_LOCKOUT_MS: u32 = 180_000;
+
+/// Least audio the ring holds ahead of the DMA. Producers run from the
+/// cooperative scheduler, so this has to outlast the longest any other task
+/// holds it up: a display flush takes about 50 ms.
+pub const RING_MS: u32 = 150;
+
+/// Buffers in the ring: enough queued for `RING_MS` at `SAMPLE_RATE`, plus
+/// the one playing.
+pub const NUM_BUFS: usize = ms_to_samples(RING_MS, SAMPLE_RATE).div_ceil(BUF_LEN as u32) as usize + 1;
+
+const _: () = assert!(
+    (NUM_BUFS - 1) * BUF_LEN >= ms_to_samples(RING_MS, SAMPLE_RATE) as usize,
+    "audio ring too short for RING_MS at SAMPLE_RATE",
+);
+
+// Ring sample storage
+static mut BUFS: [[Sample; BUF_LEN]; NUM_BUFS] = [[0; BUF_LEN]; NUM_BUFS];
+// Silence, played whenever there's nothing queued. The DMA reads it even
+// with PTT down, so it's kept in RAM: `flash::write` takes XIP away.
+#[unsafe(link_section = ".data")]
+static ZERO_BUF: [Sample; BUF_LEN] = [0; BUF_LEN];
 
 pub struct AudioOut {
     xfer: Option<DmaTransfer>,
-    use_ping: bool,
-    dma_busy: AtomicBool,
     ptt_pin: PttWrapper,
+    sample_rate: u32,
+
+    // Ring state. `head` is the next buffer to fill, `tail` the next to play.
+    head: usize,
+    tail: usize,
+    queued: usize,
+    playing: bool,
+    // A transmission is in progress: more buffers are coming until the
+    // producer calls `end_transmission`. Running dry before then is an
+    // underrun, not the end, so PTT stays keyed over it.
+    sending: bool,
+    underrun: bool,
+
+    // PTT state, and silent buffers still to play before the first queued
+    // one and after the last
+    keyed_up: bool,
+    lead_bufs: u32,
+    tail_bufs: u32,
+    lead_left: u32,
+    tail_left: u32,
+
+    // Transmit timeout, all in samples: how long PTT has been keyed, the
+    // limits, and how much lockout is left. Counted here rather than by the
+    // modem so a fault anywhere above can't hold PTT down.
+    keyed: u32,
+    max_keyed: u32,
+    lockout_len: u32,
+    lockout: u32,
+    timeouts: u32,
+    // Timeouts and underruns
+    tx_faults: u32,
 }
 
 #[allow(static_mut_refs)]
 impl AudioOut {
     /// Creates the PIO program, state-machine, DMA channel and launches the
-    /// first silent transfer.
+    /// first silent transfer. `sample_rate` must be one of `RATES`.
     pub fn new(
-        mut dma: Channels,
+        mut ch: Channel<CH0>,
         mut pio: PIO<PIO0>,
         sm: UninitStateMachine<(PIO0, SM0)>,
         ptt_pin: PttWrapper,
         clocks: &ClocksManager,
+        sample_rate: u32,
     ) -> Self {
+        assert!(RATES.contains(&sample_rate), "unsupported audio sample rate");
+
         // 1. Set up PIO program
         let program_with_defines = pio_proc::pio_file!("./src/audio_i2s.pio");
         let entry_point = program_with_defines.public_defines.entry_point as u8;
@@ -69,14 +164,13 @@ impl AudioOut {
             (22, PinDir::Output),
         ]);
 
-        // 8 kHz * 16 bits * 2 (stereo) * 2 (both edges)
-        let bclk = SAMPLE_RATE * 16 * 2 * 2;
+        // rate = sys_clk / (PIO_CYCLES_PER_SAMPLE * (int + frac / 256))
         let sys_clk = clocks.system_clock.freq().to_Hz();
-        let divisor = sys_clk as f32 / bclk as f32;
+        let (div_q8, sample_rate) = pio_divisor(sys_clk, sample_rate);
 
-        println!("BCLK: {}\nSYS_CLK: {}\ndivisor: {}", bclk, sys_clk, divisor);
+        println!("Audio rate: {} (div {}+{}/256)", sample_rate, div_q8 >> 8, div_q8 & 0xFF);
 
-        sm.set_clock_divisor(divisor);
+        sm.clock_divisor_fixed_point((div_q8 >> 8) as u16, div_q8 as u8);
 
         // Jump to entry_point
         let jmp = Instruction {
@@ -89,38 +183,107 @@ impl AudioOut {
         sm.start();
         // 2. Set up DMA channel
         // Enable IRQ
-        dma.ch0.enable_irq0();
+        ch.enable_irq0();
 
         // First silent transfer so the PIO can begin
-        let xfer = single_buffer::Config::new(dma.ch0, &ZERO_BUF, tx).start();
+        let xfer = single_buffer::Config::new(ch, &ZERO_BUF, tx).start();
 
         unsafe { pac::NVIC::unmask(pac::interrupt::DMA_IRQ_0) };
 
         Self {
             xfer: Some(xfer),
-            use_ping: true,
-            dma_busy: AtomicBool::new(true),
             ptt_pin,
+            sample_rate,
+            head: 0,
+            tail: 0,
+            queued: 0,
+            playing: false,
+            sending: false,
+            underrun: false,
+            keyed_up: false,
+            lead_bufs: 0,
+            tail_bufs: 0,
+            lead_left: 0,
+            tail_left: 0,
+            keyed: 0,
+            max_keyed: ms_to_samples(TX_TIMEOUT_MS, sample_rate),
+            lockout_len: ms_to_samples(TX_LOCKOUT_MS, sample_rate),
+            lockout: 0,
+            timeouts: 0,
+            tx_faults: 0,
         }
 
     }
 
-    /// Returns a `&mut` slice for the *inactive* buffer so upper-layers can
-    /// write samples directly.
-    pub fn get_free_buffer(&mut self) -> &'static mut [Sample; BUF_LEN] {
-        unsafe {
-            if self.use_ping {
-                &mut BUF_PING
-            } else {
-                &mut BUF_PONG
-            }
+    /// Returns the next buffer to fill, or `None` while the ring is full.
+    /// Upper layers write samples directly, then call `queue_filled`.
+    pub fn get_free_buffer(&mut self) -> Option<&'static mut [Sample; BUF_LEN]> {
+        // The buffer being played can't be touched until its transfer ends
+        if self.queued + self.playing as usize == NUM_BUFS {
+            return None;
         }
+        Some(unsafe { &mut BUFS[self.head] })
     }
 
-    /// Queues the *just-filled* buffer for DMA once the current transfer
-    /// finishes. Called from main-loop after filling.
+    /// Queues the *just-filled* buffer behind any others for the DMA.
+    /// Called from main-loop after filling. Thrown away during a lockout.
     pub fn queue_filled(&mut self) {
-        self.dma_busy.store(true, Ordering::SeqCst);
+        if self.lockout > 0 {
+            return;
+        }
+        self.head = (self.head + 1) % NUM_BUFS;
+        self.queued += 1;
+        self.sending = true;
+    }
+
+    /// Marks the last buffer queued as the end of the transmission, so PTT
+    /// drops once it and the tail have played.
+    pub fn end_transmission(&mut self) {
+        self.sending = false;
+    }
+
+    /// Sets the longest PTT may stay keyed, and the lockout that follows.
+    pub fn set_tx_timeout(&mut self, max_ms: u32, lockout_ms: u32) {
+        self.max_keyed = ms_to_samples(max_ms, self.sample_rate);
+        self.lockout_len = ms_to_samples(lockout_ms, self.sample_rate);
+    }
+
+    /// Sets how long PTT is keyed before the audio starts and after it ends,
+    /// rounded up to whole buffers.
+    pub fn set_ptt_timing(&mut self, lead_ms: u32, tail_ms: u32) {
+        let bufs = |ms| ms_to_samples(ms, self.sample_rate).div_ceil(BUF_LEN as u32);
+        self.lead_bufs = bufs(lead_ms);
+        self.tail_bufs = bufs(tail_ms);
+    }
+
+    /// Time from PTT key-up to the first queued sample reaching the DAC.
+    pub fn ptt_lead_ms(&self) -> u32 {
+        self.lead_bufs * BUF_LEN as u32 * 1000 / self.sample_rate
+    }
+
+    /// Whether PTT is keyed, including lead and tail.
+    pub fn keyed_up(&self) -> bool {
+        self.keyed_up
+    }
+
+    /// Whether transmit is locked out after a timeout.
+    pub fn tx_locked_out(&self) -> bool {
+        self.lockout > 0
+    }
+
+    /// Times PTT has been forced off.
+    pub fn tx_timeouts(&self) -> u32 {
+        self.timeouts
+    }
+
+    /// Timeouts, and times the ring ran dry in the middle of a transmission.
+    pub fn tx_faults(&self) -> u32 {
+        self.tx_faults
+    }
+
+    /// The rate actually achieved by the clock divisor.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
     }
 
     /// ISR helper - must be called from `DMA_IRQ_0`
@@ -130,34 +293,68 @@ impl AudioOut {
             return;
         }
 
-        let was_busy = self.dma_busy.load(Ordering::Relaxed);
-        self.dma_busy.store(false, Ordering::Relaxed);
+        self.lockout = self.lockout.saturating_sub(BUF_LEN as u32);
+
+        // Keyed too long: drop everything queued and sit out the lockout
+        if (self.queued > 0 || self.sending) && self.keyed >= self.max_keyed {
+            self.queued = 0;
+            self.tail = self.head;
+            self.sending = false;
+            self.lead_left = 0;
+            self.tail_left = 0;
+            self.lockout = self.lockout_len;
+            self.timeouts += 1;
+            self.tx_faults += 1;
+        }
 
-        let next = if was_busy {
-            if self.use_ping {
-                self.ptt_pin.key(true);
-                unsafe { &BUF_PING }
-            } else {
-                self.ptt_pin.key(true);
-                unsafe { &BUF_PONG }
+        // Key up ahead of the first queued buffer, with the lead in silence
+        if self.queued > 0 && !self.keyed_up {
+            self.keyed_up = true;
+            self.lead_left = self.lead_bufs;
+            self.ptt_pin.key(true);
+        }
+
+        // Whatever was playing is done; play the next queued buffer, or
+        // silence once the ring has run dry. PTT drops after the tail, once
+        // the transmission is over.
+        self.playing = false;
+        let next = if self.queued > 0 && self.lead_left == 0 {
+            let buf = unsafe { &BUFS[self.tail] };
+            self.tail = (self.tail + 1) % NUM_BUFS;
+            self.queued -= 1;
+            self.playing = true;
+            self.underrun = false;
+            self.tail_left = self.tail_bufs;
+            buf
+        } else if self.lead_left > 0 {
+            self.lead_left -= 1;
+            &ZERO_BUF
+        } else if self.sending && self.keyed_up {
+            // The producer fell behind. Hold PTT over the gap, and count it
+            // once.
+            if !self.underrun {
+                self.underrun = true;
+                self.tx_faults += 1;
             }
+            &ZERO_BUF
+        } else if self.tail_left > 0 {
+            self.tail_left -= 1;
+            &ZERO_BUF
         } else {
+            self.keyed_up = false;
             self.ptt_pin.key(false);
             &ZERO_BUF
         };
 
-        self.use_ping = !self.use_ping;
-
-        self.dma_busy.store(false, Ordering::SeqCst);
+        if self.keyed_up {
+            self.keyed += BUF_LEN as u32;
+        } else {
+            self.keyed = 0;
+        }
 
         // Launch next transfer
         self.xfer = Some(single_buffer::Config::new(ch, next, pio_tx).start());
     }
-
-    /// Non-blocking query so main-loop knows when it may write next chunk
-    pub fn is_busy(&self) -> bool {
-        self.dma_busy.load(Ordering::SeqCst)
-    }
 }
 
 //------------------------------------------------------------------------------
@@ -184,31 +381,110 @@ fn DMA_IRQ_0() {
 
 // Public facade for accessing the singleton -----------------------------------
 
-pub fn is_busy() -> bool {
+pub fn free_buffer() -> Option<&'static mut [Sample; BUF_LEN]> {
+    critical_section::with(|cs| {
+        AUDIO_OUT
+            .borrow(cs)
+            .borrow_mut()
+            .as_mut()
+            .and_then(|a| a.get_free_buffer())
+    })
+}
+
+pub fn queue_filled() {
+    critical_section::with(|cs| {
+        if let Some(a) = AUDIO_OUT.borrow(cs).borrow_mut().as_mut() {
+            a.queue_filled();
+        }
+    });
+}
+
+pub fn end_transmission() {
+    critical_section::with(|cs| {
+        if let Some(a) = AUDIO_OUT.borrow(cs).borrow_mut().as_mut() {
+            a.end_transmission();
+        }
+    });
+}
+
+pub fn sample_rate() -> u32 {
     critical_section::with(|cs| {
         AUDIO_OUT
             .borrow(cs)
             .borrow()
             .as_ref()
-            .map(|a| a.is_busy())
-            .unwrap_or(true)    // Treat uninitialized as busy
+            .map(|a| a.sample_rate())
+            .unwrap_or(SAMPLE_RATE)
     })
 }
 
-pub fn free_buffer() -> Option<&'static mut [Sample; BUF_LEN]> {
+pub fn set_tx_timeout(max_ms: u32, lockout_ms: u32) {
+    critical_section::with(|cs| {
+        if let Some(a) = AUDIO_OUT.borrow(cs).borrow_mut().as_mut() {
+            a.set_tx_timeout(max_ms, lockout_ms);
+        }
+    });
+}
+
+pub fn keyed_up() -> bool {
     critical_section::with(|cs| {
         AUDIO_OUT
             .borrow(cs)
-            .borrow_mut()
-            .as_mut()
-            .map(|a| a.get_free_buffer())
+            .borrow()
+            .as_ref()
+            .map(|a| a.keyed_up())
+            .unwrap_or(false)
     })
 }
 
-pub fn queue_filled() {
+pub fn tx_locked_out() -> bool {
+    critical_section::with(|cs| {
+        AUDIO_OUT
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map(|a| a.tx_locked_out())
+            .unwrap_or(false)
+    })
+}
+
+pub fn tx_timeouts() -> u32 {
+    critical_section::with(|cs| {
+        AUDIO_OUT
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map(|a| a.tx_timeouts())
+            .unwrap_or(0)
+    })
+}
+
+pub fn tx_faults() -> u32 {
+    critical_section::with(|cs| {
+        AUDIO_OUT
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map(|a| a.tx_faults())
+            .unwrap_or(0)
+    })
+}
+
+pub fn set_ptt_timing(lead_ms: u32, tail_ms: u32) {
     critical_section::with(|cs| {
         if let Some(a) = AUDIO_OUT.borrow(cs).borrow_mut().as_mut() {
-            a.queue_filled();
+            a.set_ptt_timing(lead_ms, tail_ms);
         }
     });
 }
+
+pub fn ptt_lead_ms() -> u32 {
+    critical_section::with(|cs| {
+        AUDIO_OUT
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map(|a| a.ptt_lead_ms())
+            .unwrap_or(0)
+    })
+}
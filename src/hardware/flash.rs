@@ -0,0 +1,89 @@
+//! One sector of the QSPI flash, kept for settings that outlive a reset.
+//!
+//! The sector sits at the very end of the 2 MiB part, which `memory.x`
+//! keeps out of the firmware image. It's read straight through the XIP
+//! window. Writing has to run from RAM with XIP off, so interrupts are held
+//! off for the erase and program (tens of milliseconds): no audio DMA
+//! refills and a few dropped GPS characters. Don't write while PTT is keyed,
+//! and nothing a DMA channel might be reading, such as the audio ring and
+//! its silent buffer, may live in flash.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use rp_pico::hal::rom_data;
+
+/// Smallest unit the flash can erase.
+pub const SECTOR_SIZE: usize = 4096;
+/// Smallest unit the flash can program.
+pub const PAGE_SIZE: usize = 256;
+
+const FLASH_SIZE: u32 = 2048 * 1024;
+const XIP_BASE: u32 = 0x1000_0000;
+/// Offset of our sector from the start of flash.
+const STORE_OFFSET: u32 = FLASH_SIZE - SECTOR_SIZE as u32;
+
+const BOOT2_SIZE: usize = 256;
+// 64 KiB block erase size, with the matching command, for the ROM to use
+// when it can; a single sector erases with 4 KiB commands regardless
+const BLOCK_SIZE: u32 = 1 << 16;
+const BLOCK_ERASE_CMD: u8 = 0xD8;
+
+/// The first page of the sector.
+pub fn read() -> &'static [u8; PAGE_SIZE] {
+    // SAFETY: the XIP window maps the whole flash read-only, and nothing
+    // but `write` changes this sector
+    unsafe { &*((XIP_BASE + STORE_OFFSET) as *const [u8; PAGE_SIZE]) }
+}
+
+/// Erases the sector and programs `page` at its start.
+pub fn write(page: &[u8; PAGE_SIZE]) {
+    // Look everything up while flash is still readable
+    let rom = Rom {
+        connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+        flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+        flash_range_erase: rom_data::flash_range_erase::ptr(),
+        flash_range_program: rom_data::flash_range_program::ptr(),
+        flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+    };
+
+    // The second stage bootloader sets up fast XIP again afterwards. It's
+    // position independent, so a copy in RAM will do.
+    let mut boot2 = [0u32; BOOT2_SIZE / 4];
+    // SAFETY: boot2 is the first 256 bytes of flash, read through XIP
+    unsafe {
+        core::ptr::copy_nonoverlapping(XIP_BASE as *const u32, boot2.as_mut_ptr(), boot2.len());
+    }
+
+    critical_section::with(|_| {
+        // SAFETY: interrupts are off, so nothing runs from flash while XIP
+        // is down, and the sector is outside the firmware image
+        unsafe { program_sector(&rom, STORE_OFFSET, page.as_ptr(), boot2.as_ptr()) };
+    });
+}
+
+struct Rom {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+}
+
+/// Runs from RAM: must not call anything that lives in flash.
+#[inline(never)]
+#[unsafe(link_section = ".data.ram_func")]
+unsafe fn program_sector(rom: &Rom, offset: u32, data: *const u8, boot2: *const u32) {
+    compiler_fence(Ordering::SeqCst);
+    unsafe {
+        (rom.connect_internal_flash)();
+        (rom.flash_exit_xip)();
+        (rom.flash_range_erase)(offset, SECTOR_SIZE, BLOCK_SIZE, BLOCK_ERASE_CMD);
+        (rom.flash_range_program)(offset, data, PAGE_SIZE);
+        (rom.flash_flush_cache)();
+
+        // Thumb code, so set the low bit
+        let enter_xip: unsafe extern "C" fn() = core::mem::transmute(boot2 as usize | 1);
+        enter_xip();
+    }
+    compiler_fence(Ordering::SeqCst);
+}
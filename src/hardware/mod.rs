@@ -4,6 +4,7 @@ use rp_pico::hal::pac;
 
 use hal::clocks::Clock;
 use hal::gpio::{self, Pins, FunctionPio0};
+use hal::rosc::RingOscillator;
 use hal::sio::Sio;
 use hal::timer::Timer;
 use hal::uart::{DataBits, StopBits, UartConfig, UartPeripheral};
@@ -17,6 +18,7 @@ use hal::fugit::RateExtU32;
 use embedded_hal::spi::{SpiBus, MODE_0};
 use rp_pico::hal::pio::PIOExt;
 
+use adc::{AdcIn, ADC_IN};
 use audio::{AudioOut, AUDIO_OUT};
 use embedded_graphics::pixelcolor::BinaryColor;
 use embedded_graphics::prelude::DrawTarget;
@@ -24,8 +26,11 @@ use embedded_graphics::prelude::DrawTarget;
 pub mod sharp_memory_display;
 pub use sharp_memory_display::SharpDisplay;
 
+use crate::config::PttConfig;
 use crate::hardware::uart::{UartHandler, UART_HANDLER};
+pub mod adc;
 pub mod audio;
+pub mod flash;
 mod ptt;
 pub(crate) mod uart;
 
@@ -51,13 +56,20 @@ pub type GpsUart = UartPeripheral<
     ),
 >;
 
+/// "Beacon now" button, to ground.
+pub type ButtonPin = gpio::Pin<gpio::bank0::Gpio14, gpio::FunctionSioInput, gpio::PullUp>;
+
 pub struct Hardware {
     pub display: SharpDisplay<DisplaySpi, DisplayCS>,
-    pub timer: Timer
+    pub button: ButtonPin,
+    pub timer: Timer,
+    /// Noise from the ring oscillator, to seed `Rng` so that units powered
+    /// up together don't make the same random choices.
+    pub seed: u32,
 }
 
 impl Hardware {
-    pub fn init(mut pac: Peripherals, _core: CorePeripherals) -> Self {
+    pub fn init(mut pac: Peripherals, _core: CorePeripherals, ptt: &PttConfig) -> Self {
         // Get all the usual objects ready
         let mut watchdog = Watchdog::new(pac.WATCHDOG);
         let clocks = hal::clocks::init_clocks_and_plls(
@@ -131,16 +143,44 @@ impl Hardware {
         pins.gpio21.into_function::<FunctionPio0>();
         pins.gpio22.into_function::<FunctionPio0>();
 
-        // Init the PTT pin
-        let mut ptt = ptt::PttWrapper::new(pins.gpio15);
-        ptt.key(false); // De-assert PTT
+        // Init the PTT pin, unkeyed
+        let ptt = ptt::PttWrapper::new(pins.gpio15, ptt);
 
-        let audio = AudioOut::new(dma, pio, sm, ptt, &clocks);
+        let audio = AudioOut::new(dma.ch0, pio, sm, ptt, &clocks, audio::SAMPLE_RATE);
         // IMMEDIATELY move AudioOut into the global for the IRQ
         critical_section::with(|cs| {
             AUDIO_OUT.borrow(cs).replace(Some(audio));
         });
 
+        // Init the radio audio input ------------------------------------------
+
+        let adc = AdcIn::new(
+            dma.ch1,
+            pac.ADC,
+            pins.gpio26.into_floating_input(),
+            &mut pac.RESETS,
+            &clocks,
+            adc::SAMPLE_RATE,
+        );
+        // IMMEDIATELY move AdcIn into the global for the IRQ
+        critical_section::with(|cs| {
+            ADC_IN.borrow(cs).replace(Some(adc));
+        });
+
+        // Init the button -----------------------------------------------------
+
+        let button = pins.gpio14.into_pull_up_input();
+
+        // Gather a seed --------------------------------------------------------
+
+        // One bit of the ring oscillator's jitter at a time; it's a poor source
+        // on its own, so fold in plenty
+        let rosc = RingOscillator::new(pac.ROSC).initialize();
+        let mut seed = 0u32;
+        for _ in 0..256 {
+            seed = seed.rotate_left(7) ^ rosc.get_random_bit() as u32;
+        }
+
         // Init the timer ------------------------------------------------------
 
         let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
@@ -149,7 +189,9 @@ impl Hardware {
 
         Self {
             display,
+            button,
             timer,
+            seed,
         }
 
     }
@@ -1,28 +1,46 @@
 use rp_pico::hal;
-use hal::gpio::{self, InOutPin, Pin};
+use hal::gpio::{self, FunctionSioOutput, InOutPin, Pin};
 use embedded_hal::digital::OutputPin;
 
+use crate::config::{PttConfig, PttDrive};
+
 type PttPin = Pin<gpio::bank0::Gpio15, gpio::FunctionNull, gpio::PullDown>;
 
+enum Drive {
+    PushPull(Pin<gpio::bank0::Gpio15, FunctionSioOutput, gpio::PullDown>),
+    // Driven low, or left floating for the radio's own pull-up
+    OpenDrain(InOutPin<PttPin>),
+}
+
 pub struct PttWrapper {
-    pin: InOutPin<PttPin>,
+    pin: Drive,
+    active_low: bool,
 }
 
 impl PttWrapper {
-    pub fn new(inner: PttPin) -> PttWrapper
+    pub fn new(inner: PttPin, cfg: &PttConfig) -> PttWrapper
     {
-        let iopin = InOutPin::new(inner);
-        Self {
-            pin: iopin
-        }
+        let pin = match cfg.drive {
+            PttDrive::PushPull => Drive::PushPull(inner.into_push_pull_output()),
+            PttDrive::OpenDrain => Drive::OpenDrain(InOutPin::new(inner)),
+        };
+        let mut this = Self {
+            pin,
+            active_low: cfg.active_low,
+        };
+        this.key(false);
+        this
     }
 
     pub fn key(&mut self, on: bool) {
+        let high = on != self.active_low;
+
         // Safety: Setting pin states is infallable
-        let _ = if on {
-            self.pin.set_low()
-        } else {
-            self.pin.set_high()
+        let _ = match (&mut self.pin, high) {
+            (Drive::PushPull(pin), true) => pin.set_high(),
+            (Drive::PushPull(pin), false) => pin.set_low(),
+            (Drive::OpenDrain(pin), true) => pin.set_high(),
+            (Drive::OpenDrain(pin), false) => pin.set_low(),
         };
     }
 
@@ -32,4 +50,4 @@ impl PttWrapper {
         self.pin.release()
     }
     */
-}
\ No newline at end of file
+}
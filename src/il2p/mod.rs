@@ -0,0 +1,498 @@
+//! IL2P (Improved Layer 2 Protocol) framing.
+//!
+//! An alternative to HDLC for carrying AX.25 frames: no bit-stuffing and no
+//! FCS, instead a sync word, a scrambled header and payload, and
+//! Reed-Solomon parity on every block. Frames are taken and returned in the
+//! same form `ax25::build_ui_frame` produces, so the rest of the stack does
+//! not care which framing was used on the air.
+//!
+//! UI frames with no digipeaters whose addresses fit the compact form are
+//! sent with a translated (type 1) header; anything else is carried
+//! verbatim as a type 0 payload. The payload always uses the maximum FEC
+//! level (16 parity bytes per block).
+
+mod rs;
+mod scramble;
+
+use heapless::Vec;
+
+use crate::ax25::{self, AddressField, MAX_FRAME_LEN};
+
+pub use rs::MAX_BLOCK_LEN;
+
+pub const SYNC_WORD: [u8; 3] = [0xF1, 0x5E, 0x48];
+pub const PREAMBLE: u8 = 0x55;
+
+pub const HEADER_LEN: usize = 13;
+const HEADER_PARITY: usize = 2;
+pub const ENCODED_HEADER_LEN: usize = HEADER_LEN + HEADER_PARITY;
+
+const PAYLOAD_PARITY: usize = 16;
+const MAX_BLOCK_DATA: usize = MAX_BLOCK_LEN - PAYLOAD_PARITY;
+const MAX_PAYLOAD_BLOCKS: usize = MAX_FRAME_LEN.div_ceil(MAX_BLOCK_DATA);
+
+/// Largest encoded frame (header, payload and parity) we can produce.
+pub const MAX_ENCODED_LEN: usize =
+    ENCODED_HEADER_LEN + MAX_FRAME_LEN + MAX_PAYLOAD_BLOCKS * PAYLOAD_PARITY;
+
+const CONTROL_UI: u8 = 0x03;
+const CONTROL_PF: u8 = 0x10;
+
+// Header bit fields --------------------------------------------------------
+//
+// Bits 0-5 of bytes 0-11 hold the callsigns; bits 6 and 7 carry the fields
+// below, most significant bit in the lowest byte index.
+
+#[derive(Clone, Copy)]
+struct Field {
+    bit: u8,
+    lsb_index: usize,
+    width: usize,
+}
+
+const UI: Field = Field { bit: 6, lsb_index: 0, width: 1 };
+const PID: Field = Field { bit: 6, lsb_index: 4, width: 4 };
+const CONTROL: Field = Field { bit: 6, lsb_index: 11, width: 7 };
+const FEC_LEVEL: Field = Field { bit: 7, lsb_index: 0, width: 1 };
+const HDR_TYPE: Field = Field { bit: 7, lsb_index: 1, width: 1 };
+const PAYLOAD_COUNT: Field = Field { bit: 7, lsb_index: 11, width: 10 };
+
+fn set_field(hdr: &mut [u8; HEADER_LEN], f: Field, mut value: u16) {
+    for i in 0..f.width {
+        if value & 1 != 0 {
+            hdr[f.lsb_index - i] |= 1 << f.bit;
+        }
+        value >>= 1;
+    }
+}
+
+fn get_field(hdr: &[u8; HEADER_LEN], f: Field) -> u16 {
+    let mut value = 0;
+    for i in (0..f.width).rev() {
+        value = (value << 1) | ((hdr[f.lsb_index - i] >> f.bit) & 1) as u16;
+    }
+    value
+}
+
+/// AX.25 PID values with an exact IL2P equivalent.
+const PID_MAP: [(u8, u8); 9] = [
+    (0x01, 0x3), // ISO 8208 / X.25 PLP
+    (0x06, 0x4), // Compressed TCP/IP
+    (0x07, 0x5), // Uncompressed TCP/IP
+    (0x08, 0x6), // Segmentation fragment
+    (0xCC, 0xB), // ARPA Internet Protocol
+    (0xCD, 0xC), // ARPA Address Resolution
+    (0xCE, 0xD), // FlexNet
+    (0xCF, 0xE), // TheNET
+    (0xF0, 0xF), // No layer 3
+];
+
+fn encode_pid(pid: u8) -> Option<u8> {
+    PID_MAP.iter().find(|(ax, _)| *ax == pid).map(|(_, il)| *il)
+}
+
+fn decode_pid(code: u8) -> Option<u8> {
+    PID_MAP.iter().find(|(_, il)| *il == code).map(|(ax, _)| *ax)
+}
+
+/// Callsign as SIXBIT, if the address survives the round trip through a
+/// type 1 header (command/response bits clear, reserved bits set).
+fn sixbit_callsign(address: &AddressField) -> Option<([u8; 6], u8)> {
+    if address.raw()[6] & 0xE0 != 0x60 {
+        return None;
+    }
+    let (call, ssid) = address.callsign();
+    let mut out = [0u8; 6];
+    for (o, &c) in out.iter_mut().zip(call.iter()) {
+        if !(0x20..=0x5F).contains(&c) {
+            return None;
+        }
+        *o = c - 0x20;
+    }
+    Some((out, ssid))
+}
+
+/// Builds a type 1 header for `frame` (without FCS), or `None` if it needs
+/// to go out as a type 0 payload.
+fn translate_header(frame: &[u8]) -> Option<([u8; HEADER_LEN], &[u8])> {
+    // Exactly two addresses, then control and PID
+    if frame.len() < 16 || frame[13] & 0x01 == 0 {
+        return None;
+    }
+    let dest = AddressField::from_raw(frame[0..7].try_into().ok()?);
+    let src = AddressField::from_raw(frame[7..14].try_into().ok()?);
+    let control = frame[14];
+    if control & !CONTROL_PF != CONTROL_UI {
+        return None;
+    }
+    let pid = encode_pid(frame[15])?;
+    let info = &frame[16..];
+
+    let (dest_call, dest_ssid) = sixbit_callsign(&dest)?;
+    let (src_call, src_ssid) = sixbit_callsign(&src)?;
+
+    let mut hdr = [0u8; HEADER_LEN];
+    hdr[0..6].copy_from_slice(&dest_call);
+    hdr[6..12].copy_from_slice(&src_call);
+    hdr[12] = (dest_ssid << 4) | src_ssid;
+
+    set_field(&mut hdr, UI, 1);
+    set_field(&mut hdr, PID, pid as u16);
+    set_field(&mut hdr, CONTROL, if control & CONTROL_PF != 0 { 0x40 } else { 0 });
+    set_field(&mut hdr, HDR_TYPE, 1);
+    set_field(&mut hdr, PAYLOAD_COUNT, info.len() as u16);
+
+    Some((hdr, info))
+}
+
+/// Rebuilds the AX.25 address, control and PID fields from a type 1 header.
+fn restore_header(
+    hdr: &[u8; HEADER_LEN],
+    frame: &mut Vec<u8, MAX_FRAME_LEN>,
+) -> Result<(), ()> {
+    if get_field(hdr, UI) != 1 {
+        // Only UI frames are translated on the way out
+        return Err(());
+    }
+
+    let address = |chars: &[u8], ssid: u8| -> Result<AddressField, ()> {
+        let mut call = [b' '; 6];
+        for (c, &s) in call.iter_mut().zip(chars.iter()) {
+            *c = (s & 0x3F) + 0x20;
+        }
+        let text = core::str::from_utf8(&call).map_err(|_| ())?;
+        AddressField::from_text(text.trim_end(), ssid)
+    };
+
+    let dest = address(&hdr[0..6], hdr[12] >> 4)?;
+    let src = address(&hdr[6..12], hdr[12] & 0x0F)?;
+
+    let mut dest_raw = dest.raw();
+    dest_raw[6] &= !0x01;
+    let mut src_raw = src.raw();
+    src_raw[6] |= 0x01;
+
+    let pf = get_field(hdr, CONTROL) & 0x40 != 0;
+    let pid = decode_pid(get_field(hdr, PID) as u8).ok_or(())?;
+
+    frame.extend_from_slice(&dest_raw).map_err(|_| ())?;
+    frame.extend_from_slice(&src_raw).map_err(|_| ())?;
+    frame.push(if pf { CONTROL_UI | CONTROL_PF } else { CONTROL_UI }).map_err(|_| ())?;
+    frame.push(pid).map_err(|_| ())
+}
+
+/// Split of the payload into RS blocks: larger blocks are sent first.
+struct PayloadLayout {
+    blocks: usize,
+    small_len: usize,
+    large_count: usize,
+}
+
+impl PayloadLayout {
+    fn new(count: usize) -> Self {
+        if count == 0 {
+            return Self { blocks: 0, small_len: 0, large_count: 0 };
+        }
+        let blocks = count.div_ceil(MAX_BLOCK_DATA);
+        let small_len = count / blocks;
+        Self {
+            blocks,
+            small_len,
+            large_count: count - blocks * small_len,
+        }
+    }
+
+    fn block_len(&self, idx: usize) -> usize {
+        if idx < self.large_count { self.small_len + 1 } else { self.small_len }
+    }
+
+    fn encoded_len(&self, count: usize) -> usize {
+        count + self.blocks * PAYLOAD_PARITY
+    }
+}
+
+/// Scrambles `data` into `out` and appends `nparity` RS parity bytes.
+/// Returns the encoded block length.
+fn encode_block(data: &[u8], out: &mut [u8; MAX_BLOCK_LEN], nparity: usize) -> usize {
+    let len = data.len();
+    let (block, parity) = out.split_at_mut(len);
+    block.copy_from_slice(data);
+    scramble::scramble(block);
+    rs::encode(block, &mut parity[..nparity]);
+    len + nparity
+}
+
+/// Incremental IL2P encoder.
+///
+/// Produces the header block and then each payload block on demand, so the
+/// caller only ever needs room for one encoded block. It does not hold the
+/// frame itself; pass the same frame to every call.
+pub struct Encoder {
+    hdr: [u8; HEADER_LEN],
+    layout: PayloadLayout,
+    next_block: usize,
+    offset: usize,
+}
+
+impl Encoder {
+    /// Prepares to encode an AX.25 frame as built by `ax25::build_ui_frame`
+    /// (FCS included).
+    pub fn new(frame: &[u8]) -> Result<Self, ()> {
+        if frame.len() < 2 {
+            return Err(());
+        }
+        let frame = &frame[..frame.len() - 2]; // IL2P carries no FCS
+
+        let (mut hdr, payload_len) = match translate_header(frame) {
+            Some((hdr, info)) => (hdr, info.len()),
+            None => {
+                let mut hdr = [0u8; HEADER_LEN];
+                set_field(&mut hdr, PAYLOAD_COUNT, frame.len() as u16);
+                (hdr, frame.len())
+            }
+        };
+        set_field(&mut hdr, FEC_LEVEL, 1);
+
+        Ok(Self {
+            hdr,
+            layout: PayloadLayout::new(payload_len),
+            next_block: 0,
+            offset: frame.len() - payload_len,
+        })
+    }
+
+    /// Writes the next encoded block of `frame` into `out` and returns its
+    /// length, or `None` once the whole frame has been produced.
+    pub fn next_block(&mut self, frame: &[u8], out: &mut [u8; MAX_BLOCK_LEN]) -> Option<usize> {
+        let idx = self.next_block;
+        if idx > self.layout.blocks {
+            return None;
+        }
+        self.next_block += 1;
+
+        if idx == 0 {
+            return Some(encode_block(&self.hdr, out, HEADER_PARITY));
+        }
+
+        let len = self.layout.block_len(idx - 1);
+        let data = &frame[self.offset..self.offset + len];
+        self.offset += len;
+        Some(encode_block(data, out, PAYLOAD_PARITY))
+    }
+}
+
+/// Encodes an AX.25 frame (FCS included) into IL2P header and payload
+/// blocks, not including the sync word.
+pub fn encode(frame: &[u8]) -> Result<Vec<u8, MAX_ENCODED_LEN>, ()> {
+    let mut encoder = Encoder::new(frame)?;
+    let mut block = [0u8; MAX_BLOCK_LEN];
+    let mut out = Vec::new();
+    while let Some(len) = encoder.next_block(frame, &mut block) {
+        out.extend_from_slice(&block[..len]).map_err(|_| ())?;
+    }
+    Ok(out)
+}
+
+fn correct_block<'a>(
+    encoded: &[u8],
+    buf: &'a mut [u8; MAX_BLOCK_LEN],
+    data_len: usize,
+    nparity: usize,
+) -> Result<&'a [u8], ()> {
+    let len = data_len + nparity;
+    buf[..len].copy_from_slice(encoded.get(..len).ok_or(())?);
+    rs::decode(&mut buf[..len], nparity)?;
+    scramble::descramble(&mut buf[..data_len]);
+    Ok(&buf[..data_len])
+}
+
+/// Decodes an IL2P frame starting right after the sync word, correcting
+/// what errors the parity allows. Returns the AX.25 frame with a freshly
+/// computed FCS.
+pub fn decode(encoded: &[u8]) -> Result<Vec<u8, MAX_FRAME_LEN>, ()> {
+    let mut buf = [0u8; MAX_BLOCK_LEN];
+    let hdr: [u8; HEADER_LEN] = correct_block(encoded, &mut buf, HEADER_LEN, HEADER_PARITY)?
+        .try_into()
+        .map_err(|_| ())?;
+
+    if get_field(&hdr, FEC_LEVEL) != 1 {
+        // We only speak the maximum FEC level
+        return Err(());
+    }
+
+    let count = get_field(&hdr, PAYLOAD_COUNT) as usize;
+    let layout = PayloadLayout::new(count);
+    if encoded.len() < ENCODED_HEADER_LEN + layout.encoded_len(count) {
+        return Err(());
+    }
+
+    let mut frame = Vec::<u8, MAX_FRAME_LEN>::new();
+    if get_field(&hdr, HDR_TYPE) == 1 {
+        restore_header(&hdr, &mut frame)?;
+    }
+
+    let mut rest = &encoded[ENCODED_HEADER_LEN..];
+    for idx in 0..layout.blocks {
+        let len = layout.block_len(idx);
+        let data = correct_block(rest, &mut buf, len, PAYLOAD_PARITY)?;
+        frame.extend_from_slice(data).map_err(|_| ())?;
+        rest = &rest[len + PAYLOAD_PARITY..];
+    }
+
+    ax25::push_fcs(&mut frame)?;
+    Ok(frame)
+}
+
+/// Total encoded length (header, payload and parity) announced by an
+/// encoded header.
+fn encoded_len(encoded_header: &[u8]) -> Result<usize, ()> {
+    let mut buf = [0u8; MAX_BLOCK_LEN];
+    let hdr: [u8; HEADER_LEN] = correct_block(encoded_header, &mut buf, HEADER_LEN, HEADER_PARITY)?
+        .try_into()
+        .map_err(|_| ())?;
+    let count = get_field(&hdr, PAYLOAD_COUNT) as usize;
+    Ok(ENCODED_HEADER_LEN + PayloadLayout::new(count).encoded_len(count))
+}
+
+const SYNC: u32 = (SYNC_WORD[0] as u32) << 16 | (SYNC_WORD[1] as u32) << 8 | SYNC_WORD[2] as u32;
+const SYNC_MASK: u32 = 0x00FF_FFFF;
+
+/// IL2P deframer: hunts for the sync word, then collects and decodes the
+/// header and payload blocks.
+///
+/// Takes received line levels as data bits (no NRZI decoding). Either
+/// polarity is accepted; it is fixed by whichever form of the sync word
+/// turns up.
+pub struct Deframer {
+    shift: u32,
+    invert: Option<bool>,
+    buf: Vec<u8, MAX_ENCODED_LEN>,
+    acc: u8,
+    nbits: u8,
+    needed: usize,
+}
+
+impl Deframer {
+    pub fn new() -> Self {
+        Self {
+            shift: 0,
+            invert: None,
+            buf: Vec::new(),
+            acc: 0,
+            nbits: 0,
+            needed: ENCODED_HEADER_LEN,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.shift = 0;
+        self.invert = None;
+        self.buf.clear();
+        self.acc = 0;
+        self.nbits = 0;
+        self.needed = ENCODED_HEADER_LEN;
+    }
+
+    /// Feed one received bit. Returns the decoded frame (with FCS) once the
+    /// last block of a frame is in.
+    pub fn push_bit(&mut self, bit: bool) -> Option<Vec<u8, MAX_FRAME_LEN>> {
+        let Some(invert) = self.invert else {
+            self.shift = ((self.shift << 1) | bit as u32) & SYNC_MASK;
+            if self.shift == SYNC {
+                self.invert = Some(false);
+            } else if self.shift == !SYNC & SYNC_MASK {
+                self.invert = Some(true);
+            }
+            return None;
+        };
+
+        self.acc = (self.acc << 1) | (bit ^ invert) as u8;
+        self.nbits += 1;
+        if self.nbits < 8 {
+            return None;
+        }
+        self.nbits = 0;
+
+        if self.buf.push(self.acc).is_err() {
+            self.reset();
+            return None;
+        }
+
+        if self.buf.len() == ENCODED_HEADER_LEN {
+            match encoded_len(&self.buf) {
+                Ok(len) if len <= MAX_ENCODED_LEN => self.needed = len,
+                _ => {
+                    self.reset();
+                    return None;
+                }
+            }
+        }
+
+        if self.buf.len() < self.needed {
+            return None;
+        }
+
+        let frame = decode(&self.buf).ok();
+        self.reset();
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ui_frame(digipeaters: &[AddressField], info: &[u8]) -> Vec<u8, MAX_FRAME_LEN> {
+        let dest = AddressField::from_text("APZ", 0).unwrap();
+        let src = AddressField::from_text("N0CALL", 1).unwrap();
+        ax25::build_ui_frame(dest, src, digipeaters, info).unwrap()
+    }
+
+    #[test]
+    fn type1_header_round_trip() {
+        let frame = ui_frame(&[], b"!4903.50N/07201.75W-Test");
+        let encoded = encode(&frame).unwrap();
+
+        // Translated header: only the info field goes in the payload
+        assert_eq!(encoded.len(), ENCODED_HEADER_LEN + 24 + PAYLOAD_PARITY);
+        assert_eq!(decode(&encoded).unwrap(), frame);
+    }
+
+    #[test]
+    fn type0_round_trip_with_digipeaters() {
+        let digis = [AddressField::from_text("WIDE1", 1).unwrap()];
+        let frame = ui_frame(&digis, b"!4903.50N/07201.75W-Test");
+        let encoded = encode(&frame).unwrap();
+
+        assert_eq!(encoded.len(), ENCODED_HEADER_LEN + frame.len() - 2 + PAYLOAD_PARITY);
+        assert_eq!(decode(&encoded).unwrap(), frame);
+    }
+
+    #[test]
+    fn multi_block_payload_corrects_errors() {
+        let info = [b'x'; ax25::MAX_INFO_LEN];
+        let frame = ui_frame(&[], &info);
+        let mut encoded = encode(&frame).unwrap();
+
+        // One error in the header, eight in each payload block
+        let last = encoded.len() - 1;
+        encoded[3] ^= 0xFF;
+        for i in 0..8 {
+            encoded[ENCODED_HEADER_LEN + i * 3] ^= 0x5A;
+            encoded[last - i * 5] ^= 0xA5;
+        }
+
+        assert_eq!(decode(&encoded).unwrap(), frame);
+    }
+
+    #[test]
+    fn rejects_uncorrectable_header() {
+        let frame = ui_frame(&[], b">status");
+        let mut encoded = encode(&frame).unwrap();
+        encoded[0] ^= 0x01;
+        encoded[1] ^= 0x01;
+        encoded[2] ^= 0x01;
+
+        assert!(decode(&encoded).is_err());
+    }
+}
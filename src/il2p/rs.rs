@@ -0,0 +1,223 @@
+//! Reed-Solomon codec over GF(2^8) as used by IL2P.
+//!
+//! Field polynomial 0x11D, first consecutive root 0, primitive element 1.
+//! Blocks are systematic: data bytes followed by the parity bytes, with the
+//! first byte on the air being the highest-degree coefficient.
+
+const FIELD_POLY: u16 = 0x11D;
+
+pub const MAX_PARITY: usize = 16;
+pub const MAX_BLOCK_LEN: usize = 255;
+
+struct Tables {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+static GF: Tables = {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= FIELD_POLY;
+        }
+        i += 1;
+    }
+    // Duplicate so `exp[a + b]` never needs a modulo
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+
+    Tables { exp, log }
+};
+
+#[inline]
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    GF.exp[GF.log[a as usize] as usize + GF.log[b as usize] as usize]
+}
+
+#[inline]
+fn div(a: u8, b: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    GF.exp[GF.log[a as usize] as usize + 255 - GF.log[b as usize] as usize]
+}
+
+#[inline]
+fn alpha_pow(n: usize) -> u8 {
+    GF.exp[n % 255]
+}
+
+/// Generator polynomial, `g[i]` is the coefficient of `x^i`; `g[nroots] == 1`.
+fn generator(nroots: usize) -> [u8; MAX_PARITY + 1] {
+    let mut g = [0u8; MAX_PARITY + 1];
+    g[0] = 1;
+
+    // Multiply in (x + a^i) for each root
+    for i in 0..nroots {
+        let root = alpha_pow(i);
+        for j in (1..=i + 1).rev() {
+            g[j] = g[j - 1] ^ mul(g[j], root);
+        }
+        g[0] = mul(g[0], root);
+    }
+
+    g
+}
+
+/// Computes `parity.len()` parity bytes over `data`.
+pub fn encode(data: &[u8], parity: &mut [u8]) {
+    let nroots = parity.len();
+    debug_assert!(nroots <= MAX_PARITY);
+    debug_assert!(data.len() + nroots <= MAX_BLOCK_LEN);
+
+    let g = generator(nroots);
+    parity.fill(0);
+
+    // Long division by g(x); `parity[0]` holds the highest-degree term
+    for &d in data {
+        let fb = d ^ parity[0];
+        parity.copy_within(1.., 0);
+        parity[nroots - 1] = 0;
+        if fb != 0 {
+            for (i, p) in parity.iter_mut().enumerate() {
+                *p ^= mul(fb, g[nroots - 1 - i]);
+            }
+        }
+    }
+}
+
+/// Corrects `block` (data followed by `nroots` parity bytes) in place.
+///
+/// Returns the number of corrected bytes, or `Err` if the block is
+/// uncorrectable.
+pub fn decode(block: &mut [u8], nroots: usize) -> Result<usize, ()> {
+    let n = block.len();
+    if nroots == 0 || nroots > MAX_PARITY || n > MAX_BLOCK_LEN || n <= nroots {
+        return Err(());
+    }
+
+    // Syndromes S_j = R(a^j)
+    let mut syn = [0u8; MAX_PARITY];
+    let mut clean = true;
+    for (j, s) in syn.iter_mut().enumerate().take(nroots) {
+        let x = alpha_pow(j);
+        let mut acc = 0u8;
+        for &b in block.iter() {
+            acc = mul(acc, x) ^ b;
+        }
+        *s = acc;
+        clean &= acc == 0;
+    }
+    if clean {
+        return Ok(0);
+    }
+
+    // Berlekamp-Massey: error locator lambda(x), lambda[0] == 1
+    let mut lambda = [0u8; MAX_PARITY + 1];
+    let mut prev = [0u8; MAX_PARITY + 1];
+    lambda[0] = 1;
+    prev[0] = 1;
+    let mut len = 0usize;
+    let mut shift = 1usize;
+    let mut prev_disc = 1u8;
+
+    for k in 0..nroots {
+        let mut disc = syn[k];
+        for i in 1..=len {
+            disc ^= mul(lambda[i], syn[k - i]);
+        }
+
+        if disc == 0 {
+            shift += 1;
+            continue;
+        }
+
+        let scale = div(disc, prev_disc);
+        let saved = lambda;
+        for i in shift..=nroots {
+            lambda[i] ^= mul(scale, prev[i - shift]);
+        }
+
+        if 2 * len <= k {
+            len = k + 1 - len;
+            prev = saved;
+            prev_disc = disc;
+            shift = 1;
+        } else {
+            shift += 1;
+        }
+    }
+
+    if len == 0 || 2 * len > nroots {
+        return Err(());
+    }
+
+    // omega(x) = S(x) * lambda(x) mod x^nroots
+    let mut omega = [0u8; MAX_PARITY];
+    for i in 0..nroots {
+        let mut acc = 0u8;
+        for j in 0..=i.min(len) {
+            acc ^= mul(lambda[j], syn[i - j]);
+        }
+        omega[i] = acc;
+    }
+
+    // Chien search over the positions actually present in the (shortened)
+    // block, then Forney for the error values.
+    let mut found = 0usize;
+    for (pos, byte) in block.iter_mut().enumerate() {
+        let power = n - 1 - pos;
+        let x_inv = alpha_pow(255 - power % 255);
+
+        let mut eval = 0u8;
+        for i in (0..=len).rev() {
+            eval = mul(eval, x_inv) ^ lambda[i];
+        }
+        if eval != 0 {
+            continue;
+        }
+
+        // lambda'(x): only odd powers survive in characteristic 2
+        let mut deriv = 0u8;
+        let mut i = 1;
+        while i <= len {
+            let mut term = lambda[i];
+            for _ in 0..i - 1 {
+                term = mul(term, x_inv);
+            }
+            deriv ^= term;
+            i += 2;
+        }
+        if deriv == 0 {
+            return Err(());
+        }
+
+        let mut om = 0u8;
+        for i in (0..nroots).rev() {
+            om = mul(om, x_inv) ^ omega[i];
+        }
+
+        // e = X^(1 - fcr) * omega(X^-1) / lambda'(X^-1), with fcr = 0
+        let x = alpha_pow(power);
+        *byte ^= mul(x, div(om, deriv));
+        found += 1;
+    }
+
+    if found != len {
+        return Err(());
+    }
+
+    Ok(found)
+}
@@ -0,0 +1,74 @@
+//! IL2P block scrambler, x^9 + x^4 + 1.
+//!
+//! The transmit side is a feed-through LFSR whose output lags its input by
+//! five bits; those five bits are dropped at the start of the block and
+//! flushed out of the register at the end, so a scrambled block is the same
+//! length as its input. Bits are processed MSB-first.
+
+const TX_INIT: u16 = 0x00F;
+const RX_INIT: u16 = 0x1F0;
+const TX_LAG: usize = 5;
+
+#[inline]
+fn scramble_bit(bit: bool, state: &mut u16) -> bool {
+    let out = ((*state >> 4) ^ *state) & 1 != 0;
+    *state = (((bit as u16 ^ *state) & 1) << 9 | (*state ^ ((*state & 1) << 4))) >> 1;
+    out
+}
+
+#[inline]
+fn descramble_bit(bit: bool, state: &mut u16) -> bool {
+    let out = (bit as u16 ^ *state) & 1 != 0;
+    *state = ((*state >> 1) | ((bit as u16) << 8)) ^ ((bit as u16) << 3);
+    out
+}
+
+struct BitWriter<'a> {
+    out: &'a mut [u8],
+    pos: usize,
+}
+
+impl BitWriter<'_> {
+    fn push(&mut self, bit: bool) {
+        if bit {
+            self.out[self.pos / 8] |= 0x80 >> (self.pos % 8);
+        }
+        self.pos += 1;
+    }
+}
+
+/// Scrambles `block` in place.
+pub fn scramble(block: &mut [u8]) {
+    let mut out = [0u8; super::rs::MAX_BLOCK_LEN];
+    let len = block.len();
+    let mut w = BitWriter { out: &mut out[..len], pos: 0 };
+    let mut state = TX_INIT;
+
+    for (idx, bit) in (0..len * 8).map(|i| (i, block[i / 8] & (0x80 >> (i % 8)) != 0)) {
+        let s = scramble_bit(bit, &mut state);
+        if idx >= TX_LAG {
+            w.push(s);
+        }
+    }
+    for _ in 0..TX_LAG {
+        let s = scramble_bit(false, &mut state);
+        w.push(s);
+    }
+
+    block.copy_from_slice(&out[..len]);
+}
+
+/// Reverses [`scramble`] in place.
+pub fn descramble(block: &mut [u8]) {
+    let mut state = RX_INIT;
+    for byte in block.iter_mut() {
+        let mut out = 0u8;
+        for i in 0..8 {
+            let mask = 0x80 >> i;
+            if descramble_bit(*byte & mask != 0, &mut state) {
+                out |= mask;
+            }
+        }
+        *byte = out;
+    }
+}
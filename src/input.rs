@@ -0,0 +1,164 @@
+//! Buttons and other controls, turned into events for the tasks.
+//!
+//! `InputTask` polls the controls and puts what they mean on the queue in
+//! `Shared`. Each task takes the events it's interested in from there, so
+//! the controls needn't know who is listening. An encoder for a menu can
+//! add its own events the same way.
+
+use embedded_hal::digital::InputPin;
+
+use crate::app::Shared;
+use crate::hardware::ButtonPin;
+use crate::sched::Tickable;
+
+/// How many events can wait to be taken.
+pub const EVENT_QUEUE_LEN: usize = 8;
+
+/// How often the controls are read.
+const POLL_MS: u64 = 5;
+
+/// Something the operator asked for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// Send a beacon now, rather than waiting for the next.
+    BeaconNow,
+}
+
+/// Events waiting to be taken, oldest first.
+pub struct Events {
+    queue: heapless::Deque<Event, EVENT_QUEUE_LEN>,
+}
+
+impl Events {
+    pub const fn new() -> Self {
+        Self { queue: heapless::Deque::new() }
+    }
+
+    /// Adds `event`, dropping the oldest if nobody has taken them.
+    pub fn push(&mut self, event: Event) {
+        if self.queue.is_full() {
+            self.queue.pop_front();
+        }
+        let _ = self.queue.push_back(event);
+    }
+
+    /// Takes every waiting `event`, leaving the rest for other tasks.
+    /// Whether there were any.
+    pub fn take(&mut self, event: Event) -> bool {
+        let mut found = false;
+        for _ in 0..self.queue.len() {
+            let Some(e) = self.queue.pop_front() else { break };
+            if e == event {
+                found = true;
+            } else {
+                let _ = self.queue.push_back(e);
+            }
+        }
+        found
+    }
+}
+
+/// Debounces a switch: a change counts once it has held for `debounce_ms`.
+pub struct Debounce {
+    /// The accepted state.
+    pressed: bool,
+    /// The state last read, and when it was first read so.
+    raw: bool,
+    since: u64,
+}
+
+impl Debounce {
+    pub const fn new() -> Self {
+        Self { pressed: false, raw: false, since: 0 }
+    }
+
+    /// Takes a reading at `now` (ms). True the once a press has held.
+    pub fn update(&mut self, now: u64, pressed: bool, debounce_ms: u16) -> bool {
+        if pressed != self.raw {
+            self.raw = pressed;
+            self.since = now;
+        }
+        if self.raw != self.pressed && now - self.since >= debounce_ms as u64 {
+            self.pressed = self.raw;
+            return self.pressed;
+        }
+        false
+    }
+}
+
+pub struct InputTask {
+    button: ButtonPin,
+    debounce: Debounce,
+    next_run_at: u64,
+}
+
+impl InputTask {
+    pub fn new(button: ButtonPin) -> Self {
+        Self {
+            button,
+            debounce: Debounce::new(),
+            next_run_at: 0,
+        }
+    }
+
+    fn run(&mut self, now: u64, shared: &mut Shared) {
+        self.next_run_at = now + POLL_MS;
+
+        // Pulled up, so pressed is low
+        let pressed = self.button.is_low().unwrap_or(false);
+        if self.debounce.update(now, pressed, shared.config.input.debounce_ms) {
+            shared.events.push(Event::BeaconNow);
+        }
+    }
+}
+
+impl Tickable for InputTask {
+    fn next_run_at(&self) -> u64 {
+        self.next_run_at
+    }
+
+    fn tick(&mut self, now: u64, shared: &mut Shared) {
+        self.run(now, shared);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounces_are_ignored() {
+        let mut debounce = Debounce::new();
+        // Chatter on the way down, then held
+        let readings = [(0, true), (2, false), (4, true), (6, false), (8, true), (30, true), (38, true), (40, true)];
+        let presses: heapless::Vec<u64, 8> = readings.iter()
+            .filter(|&&(t, p)| debounce.update(t, p, 30))
+            .map(|&(t, _)| t)
+            .collect();
+        assert_eq!(presses, [38]);
+
+        // Let go, and pressed again
+        assert!(!debounce.update(100, false, 30));
+        assert!(!debounce.update(130, false, 30));
+        assert!(!debounce.update(150, true, 30));
+        assert!(debounce.update(180, true, 30));
+        assert!(!debounce.update(400, true, 30));
+    }
+
+    #[test]
+    fn events_are_taken_once() {
+        let mut events = Events::new();
+        assert!(!events.take(Event::BeaconNow));
+        events.push(Event::BeaconNow);
+        events.push(Event::BeaconNow);
+        assert!(events.take(Event::BeaconNow));
+        assert!(!events.take(Event::BeaconNow));
+
+        // Nobody listening doesn't fill the queue for good
+        for _ in 0..EVENT_QUEUE_LEN + 3 {
+            events.push(Event::BeaconNow);
+        }
+        assert!(events.take(Event::BeaconNow));
+        assert!(events.queue.is_empty());
+    }
+}
@@ -0,0 +1,278 @@
+//! Last known position, kept in flash so there's something to beacon in a
+//! tunnel or straight after power-up.
+//!
+//! The record has a magic number, a version and a CRC, so a blank or
+//! half-written sector reads back as nothing rather than as a position.
+
+use nmea::Nmea;
+
+use crate::aprs::{Coordinate, PositionReport, Timestamp};
+use crate::ax25;
+use crate::config::{LastFixConfig, StalePolicy};
+use crate::gpstime;
+
+const MAGIC: [u8; 4] = *b"LFIX";
+const VERSION: u8 = 1;
+pub const RECORD_LEN: usize = 28;
+
+const HAS_ALTITUDE: u8 = 1 << 0;
+const HAS_COURSE: u8 = 1 << 1;
+const HAS_TIME: u8 = 1 << 2;
+
+/// Put in front of the comment of a stale beacon, for `StalePolicy::Comment`.
+pub const STALE_MARKER: &str = "Last known: ";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LastFix {
+    pub latitude: i32,
+    pub longitude: i32,
+    /// Metres above mean sea level.
+    pub altitude_m: Option<i16>,
+    /// Degrees true.
+    pub course: Option<u16>,
+    /// UTC of the fix, seconds since 1970.
+    pub time: Option<i64>,
+}
+
+impl LastFix {
+    /// The current fix, if the GPS has a valid one with a position.
+    pub fn from_nmea(nmea: &Nmea) -> Option<Self> {
+        if !nmea.fix_type.is_some_and(|f| f.is_valid()) {
+            return None;
+        }
+        let (lat, lon) = (nmea.latitude?, nmea.longitude?);
+
+        Some(Self {
+            latitude: Coordinate::from_float(lat).microdegrees,
+            longitude: Coordinate::from_float(lon).microdegrees,
+            altitude_m: nmea.altitude.map(|a| a as i16),
+            course: nmea.true_course.map(|c| c as u16 % 360),
+            time: gpstime::nmea_utc_ms(nmea).map(|ms| ms.div_euclid(1_000)),
+        })
+    }
+
+    pub fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut r = [0u8; RECORD_LEN];
+        let mut flags = 0;
+        if self.altitude_m.is_some() {
+            flags |= HAS_ALTITUDE;
+        }
+        if self.course.is_some() {
+            flags |= HAS_COURSE;
+        }
+        if self.time.is_some() {
+            flags |= HAS_TIME;
+        }
+
+        r[0..4].copy_from_slice(&MAGIC);
+        r[4] = VERSION;
+        r[5] = flags;
+        r[6..10].copy_from_slice(&self.latitude.to_le_bytes());
+        r[10..14].copy_from_slice(&self.longitude.to_le_bytes());
+        r[14..16].copy_from_slice(&self.altitude_m.unwrap_or(0).to_le_bytes());
+        r[16..18].copy_from_slice(&self.course.unwrap_or(0).to_le_bytes());
+        r[18..26].copy_from_slice(&self.time.unwrap_or(0).to_le_bytes());
+        let crc = ax25::crc16(&r[..26]);
+        r[26..28].copy_from_slice(&crc.to_le_bytes());
+        r
+    }
+
+    /// Reads back a record from `encode`. Anything else gives `None`.
+    pub fn decode(r: &[u8]) -> Option<Self> {
+        let r = r.get(..RECORD_LEN)?;
+        if r[0..4] != MAGIC || r[4] != VERSION {
+            return None;
+        }
+        if ax25::crc16(&r[..26]) != u16::from_le_bytes([r[26], r[27]]) {
+            return None;
+        }
+
+        let flags = r[5];
+        let field = |flag: u8| flags & flag != 0;
+        Some(Self {
+            latitude: i32::from_le_bytes(r[6..10].try_into().unwrap()),
+            longitude: i32::from_le_bytes(r[10..14].try_into().unwrap()),
+            altitude_m: field(HAS_ALTITUDE).then(|| i16::from_le_bytes([r[14], r[15]])),
+            course: field(HAS_COURSE).then(|| u16::from_le_bytes([r[16], r[17]])),
+            time: field(HAS_TIME).then(|| i64::from_le_bytes(r[18..26].try_into().unwrap())),
+        })
+    }
+
+    /// When the fix was taken, as an APRS day/hour/minute timestamp.
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        gpstime::dhm(self.time?)
+    }
+}
+
+/// The last fix, and what we know about how old it is.
+pub struct LastKnown {
+    pub fix: Option<LastFix>,
+    /// Uptime (ms) when `fix` was taken. `None` for a fix restored from
+    /// flash, whose age is unknown until the GPS has the time again.
+    taken_at: Option<u64>,
+    /// What's in flash, to save only when the position changes. A parked
+    /// station keeps the time it got there.
+    saved: Option<LastFix>,
+}
+
+impl LastKnown {
+    pub const fn new() -> Self {
+        Self {
+            fix: None,
+            taken_at: None,
+            saved: None,
+        }
+    }
+
+    /// Starts from the record in flash, if there's a good one.
+    pub fn restore(record: &[u8]) -> Self {
+        let fix = LastFix::decode(record);
+        Self {
+            fix,
+            taken_at: None,
+            saved: fix,
+        }
+    }
+
+    /// Records a current fix at uptime `now` (ms).
+    pub fn update(&mut self, now: u64, fix: LastFix) {
+        self.fix = Some(fix);
+        self.taken_at = Some(now);
+    }
+
+    /// Age of the fix in seconds at uptime `now`, or, failing that, from
+    /// its UTC time and `utc_now`. `None` if there's no telling.
+    pub fn age_s(&self, now: u64, utc_now: Option<i64>) -> Option<u32> {
+        let fix = self.fix?;
+        match self.taken_at {
+            Some(at) => Some((now.saturating_sub(at) / 1_000) as u32),
+            None => Some(utc_now?.saturating_sub(fix.time?).clamp(0, u32::MAX as i64) as u32),
+        }
+    }
+
+    /// Whether the fix is too old to send as though it were current.
+    pub fn is_stale(&self, cfg: &LastFixConfig, now: u64, utc_now: Option<i64>) -> bool {
+        self.age_s(now, utc_now).is_none_or(|age| age > cfg.stale_after_s)
+    }
+
+    /// The fix to save, if the position has changed since the last save.
+    pub fn unsaved(&self) -> Option<LastFix> {
+        let moved = |f: &LastFix| {
+            self.saved.is_none_or(|s| (s.latitude, s.longitude) != (f.latitude, f.longitude))
+        };
+        self.fix.filter(moved)
+    }
+
+    pub fn mark_saved(&mut self, fix: LastFix) {
+        self.saved = Some(fix);
+    }
+}
+
+/// Marks a report of a stale `fix` as `policy` asks. `false` if it
+/// shouldn't be sent at all.
+pub fn mark_stale(rpt: &mut PositionReport, fix: &LastFix, policy: StalePolicy) -> bool {
+    match policy {
+        StalePolicy::Skip => false,
+        StalePolicy::Timestamp => {
+            // Without the time, there's no saying how old it is
+            rpt.timestamp = fix.timestamp();
+            rpt.timestamp.is_some()
+        }
+        StalePolicy::Comment => {
+            let mut comment = heapless::String::new();
+            let _ = comment.push_str(STALE_MARKER);
+            for c in rpt.comment.iter().flat_map(|c| c.chars()) {
+                if comment.push(c).is_err() {
+                    break;
+                }
+            }
+            rpt.comment = Some(comment);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIX: LastFix = LastFix {
+        latitude: 49_058_333,
+        longitude: -72_029_166,
+        altitude_m: Some(120),
+        course: None,
+        // 2024-03-05 14:07:00 UTC
+        time: Some(1_709_647_620),
+    };
+
+    fn cfg(stale: StalePolicy) -> LastFixConfig {
+        LastFixConfig {
+            stale,
+            ..LastFixConfig::default()
+        }
+    }
+
+    #[test]
+    fn record_round_trips() {
+        let r = FIX.encode();
+        assert_eq!(LastFix::decode(&r), Some(FIX));
+
+        // Erased flash, a flipped bit, or an old layout read as nothing
+        assert_eq!(LastFix::decode(&[0xFF; RECORD_LEN]), None);
+        let mut bad = r;
+        bad[8] ^= 0x10;
+        assert_eq!(LastFix::decode(&bad), None);
+        let mut old = r;
+        old[4] = VERSION + 1;
+        assert_eq!(LastFix::decode(&old), None);
+    }
+
+    #[test]
+    fn restored_fix_is_stale_until_the_time_is_known() {
+        let cfg = cfg(StalePolicy::Timestamp);
+        let mut last = LastKnown::restore(&FIX.encode());
+        assert_eq!(last.fix, Some(FIX));
+        assert!(last.unsaved().is_none());
+        assert!(last.is_stale(&cfg, 0, None));
+        assert!(!last.is_stale(&cfg, 0, Some(1_709_647_620 + 30)));
+
+        // A new fix ages by uptime
+        let moved = LastFix { latitude: 49_058_400, ..FIX };
+        last.update(10_000, moved);
+        assert_eq!(last.unsaved(), Some(moved));
+        assert!(!last.is_stale(&cfg, 10_000 + cfg.stale_after_s as u64 * 1_000, None));
+        assert!(last.is_stale(&cfg, 11_000 + cfg.stale_after_s as u64 * 1_000, None));
+        last.mark_saved(moved);
+        last.update(20_000, LastFix { time: Some(1_709_648_000), ..moved });
+        assert!(last.unsaved().is_none());
+    }
+
+    #[test]
+    fn stale_reports_are_marked() {
+        let rpt = || PositionReport {
+            latitude: Coordinate { microdegrees: FIX.latitude },
+            longitude: Coordinate { microdegrees: FIX.longitude },
+            symbol_table: '/',
+            symbol_code: 'n',
+            comment: Some(heapless::String::try_from("github.com/anthonydotmoe/pico-aprs-beacon").unwrap()),
+            timestamp: None,
+            messaging: false,
+            ambiguity: 0,
+        };
+
+        let mut r = rpt();
+        assert!(!mark_stale(&mut r, &FIX, StalePolicy::Skip));
+
+        let mut r = rpt();
+        assert!(mark_stale(&mut r, &FIX, StalePolicy::Timestamp));
+        let mut ts = heapless::String::<8>::new();
+        r.timestamp.unwrap().encode(&mut ts).unwrap();
+        assert_eq!(ts, "051407z");
+        let mut r = rpt();
+        assert!(!mark_stale(&mut r, &LastFix { time: None, ..FIX }, StalePolicy::Timestamp));
+
+        let mut r = rpt();
+        assert!(mark_stale(&mut r, &FIX, StalePolicy::Comment));
+        assert_eq!(r.comment.unwrap(), "Last known: github.com/anthonydotmoe/pico-a");
+    }
+}
@@ -1,5 +1,6 @@
-#![no_std]
-#![no_main]
+// Tests build for the host, with std and the usual harness
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 
 extern crate alloc;
 
@@ -9,14 +10,15 @@ use embedded_alloc::LlffHeap as Heap;
 
 use rp_pico as bsp;
 
+#[cfg(not(test))]
 use bsp::entry;
 //use defmt::*;
 use defmt_rtt as _;
 
 // Panic handler
-#[cfg(debug_assertions)]
+#[cfg(all(not(test), debug_assertions))]
 use panic_probe as _;
-#[cfg(not(debug_assertions))]
+#[cfg(all(not(test), not(debug_assertions)))]
 use panic_halt as _;
 
 use bsp::hal;
@@ -26,12 +28,31 @@ mod app;
 mod aprs;
 mod ax25;
 mod beacon;
+#[cfg(test)]
 mod bitstream;
+mod config;
+mod decay;
+mod demod;
 mod display;
+mod fixquality;
+#[cfg(any(feature = "g3ruh", test))]
+mod g3ruh;
+mod geofence;
 mod gps;
+mod gpstime;
 mod hardware;
+mod il2p;
+mod input;
+mod lastfix;
 mod modem;
+mod morse;
+mod onair;
+mod rng;
+mod rx;
 mod sched;
+mod smartbeacon;
+#[cfg(test)]
+mod wav;
 
 
 // TODO: Figure out why I need this `global_allocator`
@@ -42,12 +63,16 @@ mod sched;
 static HEAP: Heap = Heap::empty();
 */
 
+#[cfg(not(test))]
 #[global_allocator]
 static ALLOC: Dumb = Dumb {};
 
+#[cfg(not(test))]
 struct Dumb {}
 
+#[cfg(not(test))]
 use core::{alloc::GlobalAlloc, ptr::null_mut};
+#[cfg(not(test))]
 unsafe impl GlobalAlloc for Dumb {
     unsafe fn alloc(&self, _layout: core::alloc::Layout) -> *mut u8 {
         panic!("You called alloc!");
@@ -60,10 +85,11 @@ pub mod co {
     pub const MYCALL: &'static str = "N0CALL-1";
     pub const TOCALL: &'static str = "APZ   ";
     pub const UART_BUFFER_SIZE: usize = 4096;
+    pub const TX_QUEUE_LEN: usize = 4;
 }
 
 // Entry point
-#[entry]
+#[cfg_attr(not(test), entry)]
 fn main() -> ! {
     /*
     #[cfg(feature = "alloc")]
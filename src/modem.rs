@@ -1,76 +1,379 @@
+use defmt::println;
+
 use crate::app::Shared;
-use crate::ax25::TxBits;
+use crate::aprs::split_callsign_ssid;
+use crate::config::{CwIdConfig, CwIdMode, ModemConfig};
+use crate::morse::Keyer;
+use crate::onair::OnAirBits;
 use crate::hardware::audio;
 use crate::sched::Tickable;
 
 include!(concat!(env!("OUT_DIR"), "/sine_table.rs")); // Imports SINE_TABLE
+include!(concat!(env!("OUT_DIR"), "/atten_table.rs")); // Imports ATTEN_DB_Q15
 
 // Constants
-use audio::SAMPLE_RATE;
-use cortex_m::asm::wfi;
 const TABLE_SIZE: u32 = SINE_TABLE.len() as u32;
-const PHASE_FRAC: u32 = 1 << 16;
 
-const fn phase_step(freq: u32) -> u32 {
-    ((freq as u64 * TABLE_SIZE as u64 * PHASE_FRAC as u64) / SAMPLE_RATE as u64) as u32
+/// DDS step for `freq` in 32-bit phase (2^32 = one cycle). Off by less than
+/// 2^-32 of a cycle per sample, so the tones are exact to well under 1 mHz.
+const fn phase_step(freq: u32, sample_rate: u32) -> u32 {
+    (((freq as u64) << 32) / sample_rate as u64) as u32
+}
+
+/// Baud rate and tone pair the modem runs at.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ModemProfile {
+    pub baud: u32,
+    pub mark_hz: u32,
+    pub space_hz: u32,
 }
 
-const STEP_MARK:  u32 = phase_step(1200);
-const STEP_SPACE: u32 = phase_step(2200);
+impl ModemProfile {
+    /// VHF/UHF APRS: Bell 202, 1200 baud.
+    pub const BELL_202: Self = Self { baud: 1200, mark_hz: 1200, space_hz: 2200 };
+    /// HF APRS: 300 baud with a 200 Hz shift, sent as audio into an SSB rig.
+    pub const HF_300: Self = Self { baud: 300, mark_hz: 1600, space_hz: 1800 };
+}
+
+/// How often to look at DCD again while the channel is busy.
+const DCD_POLL_MS: u64 = 10;
+
+/// How often to top up the audio ring while sending. Well under one buffer,
+/// so the ring stays full. It holds `audio::RING_MS`, which is as long as
+/// any other task may keep the scheduler from coming back here without a
+/// gap on the air; the audio path keys over a gap and counts it a fault.
+const FILL_POLL_MS: u64 = 5;
 
-// Q16.16 samples per bit
-const BITS_PER_SAMPLE_Q16: u32 = ((1200u64 << 16) / (SAMPLE_RATE as u64)) as u32;
-const ONE_Q16: u32 = 1 << 16;
+/// Hard limit on a calibration tone, however long it was asked for.
+const CAL_TIMEOUT_MS: u32 = 30_000;
+
+/// Rise and fall time of the CW keying envelope, to keep clicks off the air.
+const CW_RAMP_MS: u32 = 5;
+
+/// Whole flags (8 bits each) needed to fill `ms` at `baud`.
+const fn flags_for_ms(ms: u32, baud: u32) -> usize {
+    (ms * baud).div_ceil(8 * 1000) as usize
+}
+
+/// Full-scale sine for a 32-bit phase (2^32 = one cycle).
+pub(crate) fn sine(phase: u32) -> i16 {
+    SINE_TABLE[(phase >> (32 - TABLE_SIZE.trailing_zeros())) as usize]
+}
+
+/// Q15 gain for the CTCSS tone, 0 when it's off.
+fn ctcss_gain(cfg: &ModemConfig) -> i32 {
+    if cfg.ctcss_dhz == 0 {
+        return 0;
+    }
+    cfg.ctcss_level.min(cfg.tx_level).min(i16::MAX as u16) as i32
+}
+
+/// Q15 gains for the mark and space tones from the level, twist and
+/// emphasis settings. Twist turns the quieter tone down rather than the
+/// louder one up, so neither can clip, and room is left for any CTCSS tone.
+fn tone_gains(cfg: &ModemConfig) -> (i32, i32) {
+    let max_db = ATTEN_DB_Q15.len() as i8 - 1;
+    let twist = cfg.twist_db.clamp(-max_db, max_db);
+    let atten = |db: i8| ATTEN_DB_Q15[db.max(0) as usize] as i32;
+
+    let level = cfg.tx_level.min(i16::MAX as u16) as i32 - ctcss_gain(cfg);
+    let mut mark = (level * atten(twist)) >> 15;
+    let mut space = (level * atten(-twist)) >> 15;
+
+    if cfg.preemphasis {
+        // 6 dB/octave: amplitude in proportion to frequency
+        let p = cfg.profile;
+        let top = p.mark_hz.max(p.space_hz) as i32;
+        mark = mark * p.mark_hz as i32 / top;
+        space = space * p.space_hz as i32 / top;
+    }
+
+    (mark, space)
+}
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum AfskTone { Mark, Space }
 
+/// What to send while setting deviation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CalTone {
+    Mark,
+    Space,
+    /// Mark and space on alternate bits, the "01010" pattern.
+    Alternating,
+}
+
+/// A request to key up and send a steady test tone, placed in
+/// `Shared::calibrate`. Sent without waiting for a clear channel, and never
+/// for more than `CAL_TIMEOUT_MS`.
+#[derive(Copy, Clone, Debug)]
+pub struct Calibration {
+    pub tone: CalTone,
+    pub duration_ms: u32,
+}
+
+/// Calibration progress, shared by the modulators.
+pub(crate) struct CalState {
+    tone: CalTone,
+    bits_left: u32,
+}
+
+impl CalState {
+    pub(crate) fn new(cal: Calibration, baud: u32) -> Self {
+        let ms = cal.duration_ms.min(CAL_TIMEOUT_MS);
+        Self { tone: cal.tone, bits_left: ms * baud / 1000 }
+    }
+
+    /// Whether the next bit is space, given the last one. `None` once the
+    /// time is up.
+    pub(crate) fn next(&mut self, space: bool) -> Option<bool> {
+        if self.bits_left == 0 {
+            return None;
+        }
+        self.bits_left -= 1;
+
+        Some(match self.tone {
+            CalTone::Mark => false,
+            CalTone::Space => true,
+            CalTone::Alternating => !space,
+        })
+    }
+}
+
+/// On/off keyed tone for a CW ID, shared by the modulators. Any CTCSS tone
+/// carries on under it, so Voice Alert radios hear the ID too.
+pub(crate) struct CwState {
+    keyer: Keyer<'static>,
+    key: bool,
+    done: bool,
+
+    // Unit clock, counts up by the WPM per sample and wraps at 1.2 s worth
+    // of samples (one unit at 1 WPM, by the PARIS standard)
+    unit_accum: u32,
+    unit_len: u32,
+    wpm: u32,
+
+    phase: u32,
+    step: u32,
+    gain: i32,
+    // Q15 envelope and how far it moves per sample
+    env: i32,
+    env_step: i32,
+
+    ctcss_phase: u32,
+    ctcss_step: u32,
+    ctcss_gain: i32,
+}
+
+impl CwState {
+    pub(crate) fn new(call: &'static str, cfg: &CwIdConfig, modem: &ModemConfig, sample_rate: u32) -> Self {
+        let ramp = (sample_rate * CW_RAMP_MS / 1000).max(1);
+        Self {
+            keyer: Keyer::new(call),
+            key: false,
+            done: false,
+            unit_accum: sample_rate * 6 / 5,
+            unit_len: sample_rate * 6 / 5,
+            wpm: cfg.wpm.max(1) as u32,
+            phase: 0,
+            step: phase_step(cfg.pitch_hz as u32, sample_rate),
+            // Room for the CTCSS tone, as for the packet tones
+            gain: modem.tx_level.min(i16::MAX as u16) as i32 - ctcss_gain(modem),
+            env: 0,
+            env_step: (i16::MAX as u32).div_ceil(ramp) as i32,
+            ctcss_phase: 0,
+            ctcss_step: phase_step(modem.ctcss_dhz as u32, sample_rate * 10),
+            ctcss_gain: ctcss_gain(modem),
+        }
+    }
+
+    /// Fills `buf` with keyed tone. Returns whether the ID is still going at
+    /// the end of the buffer; once it isn't, the rest is silence.
+    pub(crate) fn fill_samples(&mut self, buf: &mut [audio::Sample]) -> bool {
+        for s in buf.iter_mut() {
+            if self.unit_accum >= self.unit_len {
+                self.unit_accum -= self.unit_len;
+                match self.keyer.next() {
+                    Some(key) => self.key = key,
+                    None => (self.key, self.done) = (false, true),
+                }
+            }
+            self.unit_accum += self.wpm;
+
+            self.env = if self.key {
+                (self.env + self.env_step).min(i16::MAX as i32)
+            } else {
+                (self.env - self.env_step).max(0)
+            };
+
+            self.phase = self.phase.wrapping_add(self.step);
+            let level = (self.gain * self.env) >> 15;
+            let mut x = (sine(self.phase) as i32 * level) >> 15;
+
+            if self.ctcss_gain != 0 {
+                self.ctcss_phase = self.ctcss_phase.wrapping_add(self.ctcss_step);
+                x += (sine(self.ctcss_phase) as i32 * self.ctcss_gain) >> 15;
+            }
+
+            *s = audio::stereo(x as i16);
+        }
+
+        !(self.done && self.env == 0)
+    }
+}
+
+/// What the transmit task needs from a modulator.
+pub trait Modulator {
+    /// Picks up the profile and level settings. Only called between
+    /// transmissions, so a change never lands mid-frame.
+    fn configure(&mut self, cfg: &ModemConfig);
+
+    /// Current bit rate on the air.
+    fn baud(&self) -> u32;
+
+    /// Starts sending `bits` from the next bit period on.
+    fn start(&mut self, bits: OnAirBits);
+
+    /// Sends `cal` in place of frames, from the next bit period on.
+    fn calibrate(&mut self, cal: Calibration, cfg: &ModemConfig);
+
+    /// Sends `call` in Morse from the next buffer on. Only called while
+    /// nothing else is being sent.
+    fn identify(&mut self, call: &'static str, cfg: &CwIdConfig, modem: &ModemConfig);
+
+    /// Whether a frame, test tone or ID is in progress.
+    fn is_sending(&self) -> bool;
+
+    /// Drops whatever is being sent, at once.
+    fn abort(&mut self);
+
+    /// Fills `buf` with the next samples. Returns whether a frame, test
+    /// tone or ID is still being sent at the end of the buffer.
+    fn fill_samples(&mut self, buf: &mut [audio::Sample]) -> bool;
+}
+
 pub struct AfskModulator {
-    phase: u32,     // 16.16
-    bit_accum: u32, // 16.16
+    sample_rate: u32,
+    phase: u32,
+    // Bit clock, counts up by the baud rate per sample and wraps at
+    // `sample_rate`. Exact, so bit timing never drifts whatever the ratio.
+    bit_accum: u32,
     nrzi_level: bool,
     tone: AfskTone,
 
-    // current source
-    src: Option<TxBits>,
+    // From the profile: baud and DDS steps
+    baud: u32,
+    step_mark: u32,
+    step_space: u32,
 
-    next_run: u64,
+    // Q15 output gain per tone
+    gain_mark: i32,
+    gain_space: i32,
+
+    // Voice Alert CTCSS tone, summed under everything but test tones
+    ctcss_phase: u32,
+    ctcss_step: u32,
+    ctcss_gain: i32,
+
+    // current source: a frame, a calibration tone, or a CW ID
+    src: Option<OnAirBits>,
+    cal: Option<CalState>,
+    cw: Option<CwState>,
 }
 
 impl AfskModulator {
-    pub fn new() -> Self {
-        Self {
+    /// A modulator for an output running at `sample_rate`, which should be
+    /// the rate the DAC actually achieved rather than the one asked for.
+    pub fn new(sample_rate: u32) -> Self {
+        let mut this = Self {
+            sample_rate,
             phase: 0,
             bit_accum: 0,
             nrzi_level: false,
             tone: AfskTone::Mark,
+            baud: 0,
+            step_mark: 0,
+            step_space: 0,
+            gain_mark: 0,
+            gain_space: 0,
+            ctcss_phase: 0,
+            ctcss_step: 0,
+            ctcss_gain: 0,
             src: None,
-            next_run: 0,
-        }
+            cal: None,
+            cw: None,
+        };
+        this.configure(&ModemConfig::default());
+        this
     }
+}
 
-    fn load_next(&mut self, shared: &mut Shared) -> bool {
-        if self.src.is_none() {
-            if let Some(next) = shared.txq.pop_front() {
-                self.src = Some(next);
-            }
-        }
-        self.src.is_some()
+impl Modulator for AfskModulator {
+    fn configure(&mut self, cfg: &ModemConfig) {
+        let p = cfg.profile;
+        self.baud = p.baud;
+        self.step_mark = phase_step(p.mark_hz, self.sample_rate);
+        self.step_space = phase_step(p.space_hz, self.sample_rate);
+        (self.gain_mark, self.gain_space) = tone_gains(cfg);
+        self.ctcss_step = phase_step(cfg.ctcss_dhz as u32, self.sample_rate * 10);
+        self.ctcss_gain = ctcss_gain(cfg);
     }
 
-    fn fill_one_buffer(&mut self) -> bool {
-        let Some(buf) = audio::free_buffer() else { return true; };
+    fn baud(&self) -> u32 {
+        self.baud
+    }
+
+    fn start(&mut self, bits: OnAirBits) {
+        self.src = Some(bits);
+    }
 
-        let mut tx_active = self.src.is_some();
+    fn calibrate(&mut self, cal: Calibration, cfg: &ModemConfig) {
+        self.configure(cfg);
+        self.cal = Some(CalState::new(cal, self.baud));
+    }
+
+    fn identify(&mut self, call: &'static str, cfg: &CwIdConfig, modem: &ModemConfig) {
+        self.cw = Some(CwState::new(call, cfg, modem, self.sample_rate));
+    }
+
+    fn is_sending(&self) -> bool {
+        self.src.is_some() || self.cal.is_some() || self.cw.is_some()
+    }
+
+    fn abort(&mut self) {
+        self.src = None;
+        self.cal = None;
+        self.cw = None;
+    }
+
+    fn fill_samples(&mut self, buf: &mut [audio::Sample]) -> bool {
+        if let Some(cw) = self.cw.as_mut() {
+            let still_tx = cw.fill_samples(buf);
+            if !still_tx {
+                self.cw = None;
+            }
+            return still_tx;
+        }
+
+        let mut tx_active = self.is_sending();
         for s in buf.iter_mut() {
             // Bit clock: advance by one sample
-            self.bit_accum = self.bit_accum.wrapping_add(BITS_PER_SAMPLE_Q16);
+            self.bit_accum += self.baud;
 
             // one bit elapsed?
-            if self.bit_accum >= ONE_Q16 {
-                self.bit_accum -= ONE_Q16;
+            if self.bit_accum >= self.sample_rate {
+                self.bit_accum -= self.sample_rate;
 
-                if let Some(src) = self.src.as_mut() {
+                if let Some(cal) = self.cal.as_mut() {
+                    if let Some(space) = cal.next(self.nrzi_level) {
+                        self.nrzi_level = space;
+                    } else {
+                        // Time's up; back to MARK like the end of a frame
+                        self.cal = None;
+                        self.nrzi_level = false;
+                        tx_active = false;
+                    }
+                } else if let Some(src) = self.src.as_mut() {
                     if let Some(bit) = src.pull_bit() {
                         if !bit { self.nrzi_level = !self.nrzi_level; } // 0 => toggle
                     } else {
@@ -88,57 +391,199 @@ impl AfskModulator {
             }
 
             // Tone DDS
-            let step = match self.tone { AfskTone::Mark => STEP_MARK, AfskTone::Space => STEP_SPACE };
+            let (step, gain) = match self.tone {
+                AfskTone::Mark => (self.step_mark, self.gain_mark),
+                AfskTone::Space => (self.step_space, self.gain_space),
+            };
             self.phase = self.phase.wrapping_add(step);
-            let index = ((self.phase >> 16) & (TABLE_SIZE - 1)) as usize;
-            *s = SINE_TABLE[index];
+            let mut x = (sine(self.phase) as i32 * gain) >> 15;
+
+            if self.ctcss_gain != 0 && self.cal.is_none() {
+                self.ctcss_phase = self.ctcss_phase.wrapping_add(self.ctcss_step);
+                x += (sine(self.ctcss_phase) as i32 * self.ctcss_gain) >> 15;
+            }
+
+            *s = audio::stereo(x as i16);
         }
 
-        audio::queue_filled();
         tx_active
     }
+}
 
-    fn transmit_blocking(&mut self, shared: &mut Shared) {
-        // Try to start with a frame; if none queued, return immediately
-        if !self.load_next(shared) {
-            return;
+/// Takes frames off `txq` (and test tone requests) and keeps the audio ring
+/// fed from a modulator.
+pub struct TxTask<M: Modulator> {
+    modem: M,
+
+    // Channel access: when we started waiting for the frame at the head of
+    // the queue
+    defer_since: Option<u64>,
+
+    // CW ID: when we last sent one, and whether a frame has gone out since
+    last_id: Option<u64>,
+    sent_since_id: bool,
+
+    // Transmit timeouts reported by the audio path so far
+    tx_timeouts: u32,
+
+    next_run: u64,
+}
+
+impl<M: Modulator> TxTask<M> {
+    pub fn new(modem: M) -> Self {
+        Self {
+            modem,
+            defer_since: None,
+            last_id: None,
+            sent_since_id: false,
+            tx_timeouts: 0,
+            next_run: 0,
         }
+    }
 
-        loop {
-            // Wait until the audio DMA can accept a buffer
-            while audio::is_busy() {
-                // Let ISRs run (DMA, UART, timer), but don't yield to other tasks
-                wfi();
+    fn load_next(&mut self, shared: &mut Shared) -> bool {
+        if !self.modem.is_sending() && let Some(next) = shared.txq.pop_front() {
+            // TXDELAY runs from key-up, part of which the audio path
+            // may already cover. Always keep one flag on either side.
+            let cfg = &shared.config.modem;
+            self.modem.configure(cfg);
+            configure_ptt(shared);
+            let baud = self.modem.baud();
+            let delay_ms = (cfg.txdelay_ms as u32).saturating_sub(audio::ptt_lead_ms());
+            let begin = flags_for_ms(delay_ms, baud).max(1);
+            let end = flags_for_ms(cfg.txtail_ms as u32, baud).max(1);
+            self.modem.start(next.into_bits(begin, end));
+            self.sent_since_id = true;
+        }
+        self.modem.is_sending()
+    }
+
+    /// Follows the frames just sent with a CW ID, if one is due. Returns
+    /// whether one was started.
+    fn load_id(&mut self, now: u64, shared: &Shared) -> bool {
+        let cfg = &shared.config.cw_id;
+        let due = match cfg.mode {
+            CwIdMode::Off => false,
+            CwIdMode::AfterEach => true,
+            CwIdMode::EveryMinutes(min) => {
+                self.last_id.is_none_or(|t| now - t >= min as u64 * 60_000)
             }
+        };
+        if !self.sent_since_id || !due {
+            return false;
+        }
 
-            // FIll and queue a buffer
-            let still_tx = self.fill_one_buffer();
+        // Just the callsign; the SSID is no part of the licence
+        let (call, _) = split_callsign_ssid(crate::co::MYCALL);
+        self.modem.identify(call, cfg, &shared.config.modem);
+        self.sent_since_id = false;
+        self.last_id = Some(now);
+        println!("TX: CW ID");
+        true
+    }
 
-            // If the current frame ended inside this buffer, see if there's another frame queued
-            if !still_tx {
-                if !self.load_next(shared) {
-                    // No more frames
-                    break;
-                }
+    /// p-persistent CSMA for the frame at the head of the queue. Returns
+    /// whether to key up now; otherwise `next_run` is set for the next try.
+    fn channel_access(&mut self, now: u64, shared: &mut Shared) -> bool {
+        let cfg = &shared.config.modem;
+        let since = *self.defer_since.get_or_insert(now);
+
+        if shared.dcd {
+            if now - since >= cfg.max_defer_ms as u64 {
+                // Someone is hogging the channel (or it's a stuck carrier)
+                shared.txq.pop_front();
+                self.defer_since = None;
+                println!("TX: channel busy for {} ms, frame dropped", now - since);
+            }
+            self.next_run = now + DCD_POLL_MS;
+            return false;
+        }
+
+        shared.rng.mix(now as u32);
+        if (shared.rng.next_u32() >> 24) as u8 > cfg.persist {
+            self.next_run = now + cfg.slottime_ms as u64;
+            return false;
+        }
+
+        self.defer_since = None;
+        true
+    }
+
+    /// Fills every free audio buffer, moving straight on to the next queued
+    /// frame when one ends so back-to-back frames share a key-up. The CW ID,
+    /// when one is due, goes out after the last of them.
+    fn fill_buffers(&mut self, now: u64, shared: &mut Shared) {
+        while let Some(buf) = audio::free_buffer() {
+            // During a lockout the audio path throws buffers away, so drop
+            // whatever was being sent rather than make samples for nothing
+            if audio::tx_locked_out() {
+                self.modem.abort();
+                audio::end_transmission();
+                break;
+            }
+
+            let still_tx = self.modem.fill_samples(buf);
+            audio::queue_filled();
+
+            if !still_tx && !self.load_next(shared) && !self.load_id(now, shared) {
+                // Frame over; the ring plays out the tail and PTT drops
+                audio::end_transmission();
+                break;
             }
-            // else: continue; frame still active
         }
     }
 
     pub fn run(&mut self, now: u64, shared: &mut Shared) {
-        // If there is work in the queue (or a frame in progress), hog the CPU and run to completion
-        if self.src.is_some() || !shared.txq.is_empty() {
-            self.transmit_blocking(shared);
-            self.next_run = now + 1;
+        // The audio path forced PTT off. Whatever was queued is suspect.
+        let timeouts = audio::tx_timeouts();
+        if timeouts != self.tx_timeouts {
+            let cfg = &shared.config.modem;
+            println!("TX: keyed over {} ms, PTT forced off and TX locked out for {} ms",
+                cfg.tx_timeout_ms, cfg.tx_lockout_ms);
+            shared.txq.clear();
+            self.tx_timeouts = timeouts;
+        }
+
+        if !self.modem.is_sending() && audio::tx_locked_out() {
+            // Hold anything new until the lockout is over
+            self.next_run = now + 100;
             return;
         }
 
-        // Nothing to do; stay quiet.
-        self.next_run = now + 100;
+        // Test tones go out between frames, never in the middle of one
+        if !self.modem.is_sending() && let Some(cal) = shared.calibrate.take() {
+            println!("TX: calibration tone for {} ms", cal.duration_ms.min(CAL_TIMEOUT_MS));
+            configure_ptt(shared);
+            self.modem.calibrate(cal, &shared.config.modem);
+        }
+
+        if !self.modem.is_sending() {
+            if shared.txq.is_empty() {
+                // Nothing to do; stay quiet.
+                self.next_run = now + 100;
+                return;
+            }
+            if !self.channel_access(now, shared) {
+                return;
+            }
+            self.load_next(shared);
+        }
+
+        // Keep the ring topped up; the DMA plays it out while other tasks run
+        self.fill_buffers(now, shared);
+        self.next_run = now + FILL_POLL_MS;
     }
 }
 
-impl Tickable for AfskModulator {
+/// Hands the audio path the timeout and PTT timing for the next key-up.
+fn configure_ptt(shared: &Shared) {
+    let cfg = &shared.config.modem;
+    let ptt = &shared.config.ptt;
+    audio::set_tx_timeout(cfg.tx_timeout_ms, cfg.tx_lockout_ms);
+    audio::set_ptt_timing(ptt.lead_ms as u32, ptt.tail_ms as u32);
+}
+
+impl<M: Modulator> Tickable for TxTask<M> {
     fn next_run_at(&self) -> u64 {
         self.next_run
     }
@@ -146,5 +591,182 @@ impl Tickable for AfskModulator {
     fn tick(&mut self, now: u64, shared: &mut Shared) {
         self.run(now, shared);
     }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::audio::SAMPLE_RATE;
+
+    fn gains(tx_level: u16, twist_db: i8, preemphasis: bool) -> (i32, i32) {
+        tone_gains(&ModemConfig { tx_level, twist_db, preemphasis, ..ModemConfig::default() })
+    }
+
+    #[test]
+    fn flat_full_scale_by_default() {
+        assert_eq!(tone_gains(&ModemConfig::default()), (i16::MAX as i32, i16::MAX as i32));
+        assert_eq!(gains(16384, 0, false), (16384, 16384));
+    }
+
+    #[test]
+    fn twist_turns_the_other_tone_down() {
+        // 6 dB is about half amplitude
+        let (mark, space) = gains(32767, 6, false);
+        assert_eq!(space, 32767);
+        assert!((16300..16500).contains(&mark), "{}", mark);
+
+        let (mark, space) = gains(32767, -6, false);
+        assert_eq!(mark, 32767);
+        assert!((16300..16500).contains(&space), "{}", space);
+
+        // Out of range twist is held at the table's limit
+        assert_eq!(gains(32767, 100, false), gains(32767, 12, false));
+    }
+
+    /// Runs the modulator until it stops sending; returns the samples sent.
+    fn samples_until_idle(modem: &mut AfskModulator) -> usize {
+        let mut buf = [0 as audio::Sample; 64];
+        let mut samples = 0;
+        while modem.fill_samples(&mut buf) {
+            samples += buf.len();
+            assert!(samples < 2 * (CAL_TIMEOUT_MS * SAMPLE_RATE / 1000) as usize);
+        }
+        samples
+    }
+
+    #[test]
+    fn calibration_tone_ends_on_time() {
+        let mut modem = AfskModulator::new(SAMPLE_RATE);
+        let cal = Calibration { tone: CalTone::Alternating, duration_ms: 500 };
+        modem.calibrate(cal, &ModemConfig::default());
+
+        let expected = (500 * SAMPLE_RATE / 1000) as usize;
+        let samples = samples_until_idle(&mut modem);
+        assert!(samples.abs_diff(expected) <= 64, "{}", samples);
+    }
+
+    #[test]
+    fn calibration_tone_is_capped() {
+        let mut modem = AfskModulator::new(SAMPLE_RATE);
+        let cal = Calibration { tone: CalTone::Mark, duration_ms: u32::MAX };
+        modem.calibrate(cal, &ModemConfig::default());
+
+        let expected = (CAL_TIMEOUT_MS * SAMPLE_RATE / 1000) as usize;
+        let samples = samples_until_idle(&mut modem);
+        assert!(samples.abs_diff(expected) <= 64, "{}", samples);
+    }
+
+    #[test]
+    fn ctcss_tone_under_frame() {
+        let cfg = ModemConfig { ctcss_dhz: 1000, ctcss_level: 3277, ..ModemConfig::default() };
+        assert_eq!(tone_gains(&cfg), (32767 - 3277, 32767 - 3277));
+
+        let mut modem = AfskModulator::new(SAMPLE_RATE);
+        modem.configure(&cfg);
+        let mut frame = heapless::Vec::new();
+        while frame.extend_from_slice(b"The quick brown fox ").is_ok() {}
+        let tx = crate::onair::TxFrame::new(crate::ax25::Framing::Ax25, frame).unwrap();
+        modem.start(tx.into_bits(8, 1));
+
+        // Correlate a second (100 cycles) against 100 Hz. The packet tones
+        // average out; the CTCSS tone is what's left.
+        let len = SAMPLE_RATE as usize;
+        let step = phase_step(100, SAMPLE_RATE);
+        let (mut i, mut q, mut phase) = (0i64, 0i64, 0u32);
+        let mut buf = [0 as audio::Sample; 64];
+        for _ in 0..len / buf.len() {
+            assert!(modem.fill_samples(&mut buf));
+            for &s in buf.iter() {
+                phase = phase.wrapping_add(step);
+                i += (s as i16 as i64) * sine(phase) as i64;
+                q += (s as i16 as i64) * sine(phase.wrapping_add(1 << 30)) as i64;
+            }
+        }
+        let norm = len as i64 * 32767 / 2;
+        let (i, q) = (i / norm, q / norm);
+        let power = i * i + q * q;
+        assert!((3100 * 3100..3450 * 3450).contains(&power), "{} {}", i, q);
+    }
+
+    #[test]
+    fn preemphasis_tilts_mark_down() {
+        let (mark, space) = gains(32767, 0, true);
+        assert_eq!(space, 32767);
+        assert_eq!(mark, 32767 * 1200 / 2200);
+    }
+
+    #[test]
+    fn bit_clock_does_not_drift() {
+        // None of these rates is a whole number of samples per bit
+        for rate in [9_600u32, 22_050, 44_100] {
+            let mut modem = AfskModulator::new(rate);
+            let cal = Calibration { tone: CalTone::Mark, duration_ms: 10_000 };
+            modem.calibrate(cal, &ModemConfig::default());
+
+            // Bit k is clocked in on the sample that completes its period.
+            // The tone stops at bit 12001, and that buffer reports idle.
+            let expected = (12_001 * rate).div_ceil(1200) as usize - 1;
+            let mut buf = [0 as audio::Sample; 1];
+            let mut samples = 0;
+            while modem.fill_samples(&mut buf) {
+                samples += 1;
+            }
+            assert_eq!(samples, expected, "at {} Hz", rate);
+        }
+    }
+
+    #[test]
+    fn tone_steps_are_exact() {
+        // 1200 Hz at 44.1 kHz: 10 s of steps lands within one step of
+        // a whole number of cycles
+        let step = phase_step(1200, 44_100) as u64;
+        let phase = (step * 441_000) % (1 << 32);
+        assert!(phase.min((1 << 32) - phase) < step, "{}", phase);
+    }
+
+    #[test]
+    fn cw_id_keys_each_element() {
+        let cfg = CwIdConfig { wpm: 20, ..CwIdConfig::default() };
+        let mut cw = CwState::new("EE", &cfg, &ModemConfig::default(), SAMPLE_RATE);
+
+        // 60 ms units: dit, three units of gap, dit, then the last ramp down.
+        // Look at the middle of each unit, clear of the ramps.
+        let mut key_down = [false; 6];
+        let mut buf = [0 as audio::Sample; 1];
+        let mut samples = 0;
+        while cw.fill_samples(&mut buf) {
+            let ms = samples * 1000 / SAMPLE_RATE as usize;
+            if (10..50).contains(&(ms % 60)) {
+                key_down[ms / 60] |= (buf[0] as i16).unsigned_abs() > 16_000;
+            }
+            samples += 1;
+        }
+
+        let expected = (5 * 60 + CW_RAMP_MS) * SAMPLE_RATE / 1000;
+        assert!(samples.abs_diff(expected as usize) <= 1, "{}", samples);
+        assert_eq!(key_down[..5], [true, false, false, false, true]);
+    }
+
+    #[test]
+    fn ctcss_tone_under_cw_id() {
+        let modem = ModemConfig { ctcss_dhz: 1000, ctcss_level: 3277, ..ModemConfig::default() };
+        let mut cw = CwState::new("EE", &CwIdConfig { wpm: 20, ..CwIdConfig::default() }, &modem, SAMPLE_RATE);
+
+        // The gap between the dits is the tone alone
+        let mut buf = [0 as audio::Sample; 1];
+        let (mut samples, mut peak) = (0, 0);
+        while cw.fill_samples(&mut buf) {
+            if (70..230).contains(&(samples * 1000 / SAMPLE_RATE as usize)) {
+                peak = peak.max((buf[0] as i16).unsigned_abs());
+            }
+            samples += 1;
+        }
+        assert!((3200..=3277).contains(&peak), "{}", peak);
+    }
+
+    #[test]
+    fn txdelay_scales_with_baud() {
+        assert_eq!(flags_for_ms(500, ModemProfile::BELL_202.baud), 75);
+        assert_eq!(flags_for_ms(500, ModemProfile::HF_300.baud), 19);
+    }
 }
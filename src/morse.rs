@@ -0,0 +1,151 @@
+//! Morse code keying for the CW station ID.
+//!
+//! Text is turned into key up/down decisions one dit-length unit at a time:
+//! a dah is three units, elements are one unit apart, characters three and
+//! words seven. Characters with no Morse code are left out.
+
+/// Dot/dash pattern for `c`, or an empty one if it has none.
+fn pattern(c: u8) -> &'static [u8] {
+    let code: &'static str = match c.to_ascii_uppercase() {
+        b'A' => ".-",
+        b'B' => "-...",
+        b'C' => "-.-.",
+        b'D' => "-..",
+        b'E' => ".",
+        b'F' => "..-.",
+        b'G' => "--.",
+        b'H' => "....",
+        b'I' => "..",
+        b'J' => ".---",
+        b'K' => "-.-",
+        b'L' => ".-..",
+        b'M' => "--",
+        b'N' => "-.",
+        b'O' => "---",
+        b'P' => ".--.",
+        b'Q' => "--.-",
+        b'R' => ".-.",
+        b'S' => "...",
+        b'T' => "-",
+        b'U' => "..-",
+        b'V' => "...-",
+        b'W' => ".--",
+        b'X' => "-..-",
+        b'Y' => "-.--",
+        b'Z' => "--..",
+        b'0' => "-----",
+        b'1' => ".----",
+        b'2' => "..---",
+        b'3' => "...--",
+        b'4' => "....-",
+        b'5' => ".....",
+        b'6' => "-....",
+        b'7' => "--...",
+        b'8' => "---..",
+        b'9' => "----.",
+        b'/' => "-..-.",
+        b'-' => "-....-",
+        b'?' => "..--..",
+        b'.' => ".-.-.-",
+        _ => "",
+    };
+    code.as_bytes()
+}
+
+/// Yields whether the key is down for each unit of `text`.
+pub struct Keyer<'a> {
+    text: &'a [u8],
+    // Elements left in the current character
+    elements: &'static [u8],
+    key: bool,
+    units: u8,
+}
+
+impl<'a> Keyer<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Self {
+            text: text.as_bytes(),
+            elements: &[],
+            key: false,
+            units: 0,
+        }
+    }
+
+    /// Moves on to the next element or gap. Returns `false` at the end.
+    fn advance(&mut self) -> bool {
+        if self.key {
+            // Element done: a short gap inside a character, a longer one
+            // between characters and words
+            self.key = false;
+            self.units = if !self.elements.is_empty() {
+                1
+            } else {
+                let mut gap = 3;
+                while let Some((&c, rest)) = self.text.split_first() && pattern(c).is_empty() {
+                    if c == b' ' {
+                        gap = 7;
+                    }
+                    self.text = rest;
+                }
+                if self.text.is_empty() {
+                    return false;
+                }
+                gap
+            };
+            return true;
+        }
+
+        while self.elements.is_empty() {
+            let Some((&c, rest)) = self.text.split_first() else { return false; };
+            self.text = rest;
+            self.elements = pattern(c);
+        }
+
+        let (&e, rest) = self.elements.split_first().unwrap();
+        self.elements = rest;
+        self.key = true;
+        self.units = if e == b'-' { 3 } else { 1 };
+        true
+    }
+}
+
+impl Iterator for Keyer<'_> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        if self.units == 0 && !self.advance() {
+            return None;
+        }
+        self.units -= 1;
+        Some(self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders the keying as '=' for key down and '.' for key up.
+    fn render(text: &str) -> heapless::String<128> {
+        Keyer::new(text).map(|k| if k { '=' } else { '.' }).collect()
+    }
+
+    #[test]
+    fn element_and_character_spacing() {
+        assert_eq!(render("SOS"), "=.=.=...===.===.===...=.=.=");
+        assert_eq!(render("e"), "=");
+    }
+
+    #[test]
+    fn words_are_seven_units_apart() {
+        assert_eq!(render("E  T"), "=.......===");
+        // Leading and trailing spaces, and unknown characters, send nothing
+        assert_eq!(render(" E# "), "=");
+    }
+
+    #[test]
+    fn paris_is_fifty_units() {
+        // The standard word includes the gap to the next word
+        assert_eq!(Keyer::new("PARIS").count() + 7, 50);
+    }
+}
@@ -0,0 +1,317 @@
+//! Lazy on-air bit generation.
+//!
+//! Frames wait in the TX queue as plain frame bytes. The preamble, line
+//! coding and postamble are only produced bit by bit as the modulator pulls
+//! them, so a queued frame costs little more than the frame itself.
+
+use heapless::Vec;
+
+use crate::ax25::{Framing, MAX_FRAME_LEN};
+use crate::il2p;
+
+const HDLC_FLAG: u8 = 0x7E;
+
+/// A frame waiting to go on the air.
+pub struct TxFrame {
+    framing: Framing,
+    frame: Vec<u8, MAX_FRAME_LEN>,
+}
+
+impl TxFrame {
+    /// Wraps a frame as built by `ax25::build_ui_frame` (FCS included).
+    pub fn new(framing: Framing, frame: Vec<u8, MAX_FRAME_LEN>) -> Result<Self, ()> {
+        if framing == Framing::Il2p {
+            // Catch anything IL2P can't carry now rather than mid-transmission
+            il2p::Encoder::new(&frame)?;
+        }
+
+        Ok(Self { framing, frame })
+    }
+
+    /// Starts generating the on-air bits for this frame, with `begin_flags`
+    /// and `end_flags` fill bytes (flags, or the IL2P preamble) around it.
+    pub fn into_bits(self, begin_flags: usize, end_flags: usize) -> OnAirBits {
+        let il2p = match self.framing {
+            Framing::Ax25 => None,
+            Framing::Il2p => il2p::Encoder::new(&self.frame).ok(),
+        };
+
+        OnAirBits {
+            tx: self,
+            begin_flags,
+            end_flags,
+            phase: Phase::Preamble,
+            idx: 0,
+            byte: 0,
+            bits_left: 0,
+            ones: 0,
+            stuff_pending: false,
+            level: false,
+            il2p,
+            block: [0; il2p::MAX_BLOCK_LEN],
+            block_len: 0,
+            block_pos: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Phase {
+    Preamble,
+    Body,
+    Postamble,
+    Done,
+}
+
+/// On-air bit generator for one frame.
+///
+/// Bits come out ready for the modulator's NRZI stage: AX.25 as flags and
+/// stuffed frame bits sent LSB-first, IL2P as MSB-first levels
+/// pre-compensated for the NRZI the modulator applies.
+pub struct OnAirBits {
+    tx: TxFrame,
+    begin_flags: usize,
+    end_flags: usize,
+    phase: Phase,
+    idx: usize,
+
+    // Bit pump
+    byte: u8,
+    bits_left: u8,
+
+    // AX.25: HDLC bit-stuffing state
+    ones: u8,
+    stuff_pending: bool,
+
+    // IL2P: last line level and the block being sent
+    level: bool,
+    il2p: Option<il2p::Encoder>,
+    block: [u8; il2p::MAX_BLOCK_LEN],
+    block_len: usize,
+    block_pos: usize,
+}
+
+impl OnAirBits {
+    fn fill_byte(&self) -> u8 {
+        match self.tx.framing {
+            Framing::Ax25 => HDLC_FLAG,
+            Framing::Il2p => il2p::PREAMBLE,
+        }
+    }
+
+    fn body_byte(&mut self) -> Option<u8> {
+        let idx = self.idx;
+        self.idx += 1;
+
+        match self.tx.framing {
+            Framing::Ax25 => self.tx.frame.get(idx).copied(),
+            Framing::Il2p => {
+                if let Some(&b) = il2p::SYNC_WORD.get(idx) {
+                    return Some(b);
+                }
+                if self.block_pos == self.block_len {
+                    let encoder = self.il2p.as_mut()?;
+                    self.block_len = encoder.next_block(&self.tx.frame, &mut self.block)?;
+                    self.block_pos = 0;
+                }
+                let b = self.block[self.block_pos];
+                self.block_pos += 1;
+                Some(b)
+            }
+        }
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        loop {
+            match self.phase {
+                Phase::Preamble => {
+                    if self.idx < self.begin_flags {
+                        self.idx += 1;
+                        return Some(self.fill_byte());
+                    }
+                    self.phase = Phase::Body;
+                    self.idx = 0;
+                }
+                Phase::Body => {
+                    if let Some(b) = self.body_byte() {
+                        return Some(b);
+                    }
+                    self.phase = Phase::Postamble;
+                    self.idx = 0;
+                }
+                Phase::Postamble => {
+                    if self.idx < self.end_flags {
+                        self.idx += 1;
+                        return Some(self.fill_byte());
+                    }
+                    self.phase = Phase::Done;
+                }
+                Phase::Done => return None,
+            }
+        }
+    }
+
+    /// Pull the next bit. Returns `None` at end.
+    pub fn pull_bit(&mut self) -> Option<bool> {
+        if self.stuff_pending {
+            self.stuff_pending = false;
+            return Some(false);
+        }
+
+        if self.bits_left == 0 {
+            self.byte = self.next_byte()?;
+            self.bits_left = 8;
+        }
+        self.bits_left -= 1;
+
+        match self.tx.framing {
+            Framing::Ax25 => {
+                let bit = self.byte & 1 != 0;
+                self.byte >>= 1;
+
+                // Insert a 0 after any run of five 1s inside the frame
+                if self.phase == Phase::Body {
+                    if bit {
+                        self.ones += 1;
+                        if self.ones == 5 {
+                            self.ones = 0;
+                            self.stuff_pending = true;
+                        }
+                    } else {
+                        self.ones = 0;
+                    }
+                }
+                Some(bit)
+            }
+            Framing::Il2p => {
+                let bit = self.byte & 0x80 != 0;
+                self.byte <<= 1;
+
+                // 1 => MARK. A repeated level is a 1 to the NRZI stage.
+                let level = !bit;
+                let out = level == self.level;
+                self.level = level;
+                Some(out)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ax25::{self, AddressField};
+    use crate::bitstream::Bitstream;
+
+    // Materialized builders the generator replaced, kept as the reference
+    // for its output.
+
+    const REF_BYTES: usize = 600;
+    const BEGIN_FLAGS: usize = 75;
+    const END_FLAGS: usize = 3;
+
+    fn push_byte_lsb(bs: &mut Bitstream<REF_BYTES>, b: u8) {
+        for i in 0..8 {
+            bs.push_bit(((b >> i) & 1) != 0).unwrap();
+        }
+    }
+
+    fn reference_ax25(frame: &[u8]) -> Bitstream<REF_BYTES> {
+        let mut bs = Bitstream::new();
+        for _ in 0..BEGIN_FLAGS {
+            push_byte_lsb(&mut bs, HDLC_FLAG);
+        }
+        let mut ones: u8 = 0;
+        for &b in frame {
+            for i in 0..8 {
+                let bit = ((b >> i) & 1) != 0;
+                bs.push_bit(bit).unwrap();
+                if bit {
+                    ones += 1;
+                    if ones == 5 {
+                        bs.push_bit(false).unwrap();
+                        ones = 0;
+                    }
+                } else {
+                    ones = 0;
+                }
+            }
+        }
+        for _ in 0..END_FLAGS {
+            push_byte_lsb(&mut bs, HDLC_FLAG);
+        }
+        bs.finish().unwrap();
+        bs
+    }
+
+    fn reference_il2p(frame: &[u8]) -> Bitstream<REF_BYTES> {
+        let encoded = il2p::encode(frame).unwrap();
+        let mut bs = Bitstream::new();
+        let mut level = false;
+        let mut push = |bs: &mut Bitstream<REF_BYTES>, b: u8| {
+            for i in (0..8).rev() {
+                let l = (b >> i) & 1 == 0;
+                bs.push_bit(l == level).unwrap();
+                level = l;
+            }
+        };
+        for _ in 0..BEGIN_FLAGS {
+            push(&mut bs, il2p::PREAMBLE);
+        }
+        for &b in il2p::SYNC_WORD.iter().chain(encoded.iter()) {
+            push(&mut bs, b);
+        }
+        for _ in 0..END_FLAGS {
+            push(&mut bs, il2p::PREAMBLE);
+        }
+        bs.finish().unwrap();
+        bs
+    }
+
+    fn assert_matches(mut bits: OnAirBits, mut reference: Bitstream<REF_BYTES>) {
+        // The reference pads its last byte with zeros; only the real bits count
+        let (_, len) = reference.as_bytes();
+        for n in 0..len {
+            assert_eq!(bits.pull_bit(), reference.pull_bit(), "bit {}", n);
+        }
+        assert_eq!(bits.pull_bit(), None);
+    }
+
+    fn frame(digipeaters: &[AddressField], info: &[u8]) -> Vec<u8, MAX_FRAME_LEN> {
+        let dest = AddressField::from_text("APZ", 0).unwrap();
+        let src = AddressField::from_text("N0CALL", 7).unwrap();
+        ax25::build_ui_frame(dest, src, digipeaters, info).unwrap()
+    }
+
+    fn bits(framing: Framing, frame: Vec<u8, MAX_FRAME_LEN>) -> OnAirBits {
+        TxFrame::new(framing, frame).unwrap().into_bits(BEGIN_FLAGS, END_FLAGS)
+    }
+
+    #[test]
+    fn ax25_matches_materialized_bitstream() {
+        let digis = [AddressField::from_text("WIDE1", 1).unwrap()];
+        let frame = frame(&digis, b"!4903.50N/07201.75WbPHG0020Test 001234");
+        let reference = reference_ax25(&frame);
+
+        assert_matches(bits(Framing::Ax25, frame), reference);
+    }
+
+    #[test]
+    fn ax25_stuffs_long_runs_of_ones() {
+        let info = [0xFF; ax25::MAX_INFO_LEN];
+        let frame = frame(&[], &info);
+        let reference = reference_ax25(&frame);
+
+        assert_matches(bits(Framing::Ax25, frame), reference);
+    }
+
+    #[test]
+    fn il2p_matches_materialized_bitstream() {
+        for info in [&b">status"[..], &[b'x'; ax25::MAX_INFO_LEN][..]] {
+            let frame = frame(&[], info);
+            let reference = reference_il2p(&frame);
+
+            assert_matches(bits(Framing::Il2p, frame), reference);
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Small xorshift PRNG for channel access and timing jitter. Not for
+//! anything that needs to be unpredictable.
+
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    pub const fn new(seed: u32) -> Self {
+        // Xorshift never leaves zero
+        Self { state: if seed == 0 { 0x2545_F491 } else { seed } }
+    }
+
+    /// Stirs in outside entropy, such as the time of some external event.
+    pub fn mix(&mut self, entropy: u32) {
+        let mixed = self.state ^ entropy.wrapping_mul(0x9E37_79B9);
+        self.state = if mixed == 0 { self.state } else { mixed };
+        self.next_u32();
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+}
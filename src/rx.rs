@@ -0,0 +1,66 @@
+use defmt::println;
+
+use crate::app::Shared;
+use crate::demod::Demodulator;
+use crate::hardware::adc;
+use crate::modem::ModemProfile;
+use crate::sched::Tickable;
+
+pub struct RxTask {
+    next_run_at: u64,
+    demod: Demodulator,
+    profile: ModemProfile,
+    buf: [i16; adc::BUF_LEN],
+    overruns: u32,
+}
+
+impl RxTask {
+    pub fn new() -> Self {
+        Self {
+            next_run_at: 0,
+            demod: Demodulator::new(adc::sample_rate(), ModemProfile::BELL_202),
+            profile: ModemProfile::BELL_202,
+            buf: [0; adc::BUF_LEN],
+            overruns: 0,
+        }
+    }
+
+    fn run(&mut self, now: u64, shared: &mut Shared) {
+        // Two buffers of capture; come back well before the second one fills
+        self.next_run_at = now + 10;
+
+        // Listen with the same profile we transmit with. With the g3ruh
+        // feature that's still AFSK: there's no 9600 baud receiver, and so
+        // no DCD for a 9600 channel.
+        let profile = shared.config.modem.profile;
+        if profile != self.profile {
+            self.demod = Demodulator::new(adc::sample_rate(), profile);
+            self.profile = profile;
+        }
+
+        while adc::read_samples(&mut self.buf) {
+            for &s in self.buf.iter() {
+                if let Some(frame) = self.demod.push_sample(s) {
+                    println!("RX: {} bytes {=[u8]:02x}", frame.len(), frame[..]);
+                }
+            }
+        }
+        shared.dcd = self.demod.dcd();
+
+        let overruns = adc::overruns();
+        if overruns != self.overruns {
+            println!("RX: {} audio buffers lost", overruns - self.overruns);
+            self.overruns = overruns;
+        }
+    }
+}
+
+impl Tickable for RxTask {
+    fn next_run_at(&self) -> u64 {
+        self.next_run_at
+    }
+
+    fn tick(&mut self, now: u64, shared: &mut Shared) {
+        self.run(now, shared);
+    }
+}
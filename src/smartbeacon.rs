@@ -0,0 +1,155 @@
+//! SmartBeaconing: the beacon rate follows speed, and corners send early.
+//!
+//! Below the slow speed beacons go at the slow rate and above the fast speed
+//! at the fast rate. In between the interval shrinks in proportion to speed,
+//! so beacons land roughly the same distance apart. A change of course
+//! bigger than the turn threshold sends one straight away ("corner pegging"),
+//! so a track follows the road. The threshold widens at low speed, where the
+//! GPS course wanders.
+
+use crate::config::SmartBeaconConfig;
+
+/// Speed over ground in knots, as NMEA gives it, to whole km/h.
+pub fn knots_to_kmh(knots: f32) -> u16 {
+    // Negative or NaN casts to 0
+    (knots * 1.852 + 0.5) as u16
+}
+
+/// Interval between beacons at `speed_kmh`, in seconds.
+pub fn rate_s(cfg: &SmartBeaconConfig, speed_kmh: u16) -> u32 {
+    if speed_kmh <= cfg.slow_speed_kmh {
+        cfg.slow_rate_s as u32
+    } else if speed_kmh >= cfg.fast_speed_kmh {
+        cfg.fast_rate_s as u32
+    } else {
+        (cfg.fast_rate_s as u32 * cfg.fast_speed_kmh as u32 / speed_kmh as u32)
+            .min(cfg.slow_rate_s as u32)
+    }
+}
+
+/// Change of course, in degrees, that counts as a corner at `speed_kmh`.
+pub fn turn_threshold(cfg: &SmartBeaconConfig, speed_kmh: u16) -> u32 {
+    cfg.turn_min_deg as u32 + cfg.turn_slope as u32 / speed_kmh.max(1) as u32
+}
+
+/// Angle between two courses, 0 to 180 degrees.
+fn heading_change(a: u16, b: u16) -> u16 {
+    let d = (a % 360).abs_diff(b % 360);
+    d.min(360 - d)
+}
+
+/// When the last beacon went, and which way we were heading.
+pub struct SmartBeacon {
+    last_time: Option<u64>,
+    last_course: Option<u16>,
+}
+
+impl SmartBeacon {
+    pub const fn new() -> Self {
+        Self {
+            last_time: None,
+            last_course: None,
+        }
+    }
+
+    /// Whether a beacon is due at `now` (ms), going at `speed_kmh` on
+    /// `course` (degrees true, if the GPS has one).
+    pub fn due(&self, now: u64, cfg: &SmartBeaconConfig, speed_kmh: u16, course: Option<u16>) -> bool {
+        let Some(last) = self.last_time else { return true; };
+        let since = now.saturating_sub(last);
+
+        if since >= rate_s(cfg, speed_kmh) as u64 * 1_000 {
+            return true;
+        }
+
+        // Corners only count when moving, and not too soon after the last
+        // beacon
+        if speed_kmh <= cfg.slow_speed_kmh || since < cfg.turn_time_s as u64 * 1_000 {
+            return false;
+        }
+        match (course, self.last_course) {
+            (Some(now), Some(then)) => heading_change(now, then) as u32 > turn_threshold(cfg, speed_kmh),
+            _ => false,
+        }
+    }
+
+    /// Records a beacon sent at `now`. A missing course keeps the last one.
+    pub fn sent(&mut self, now: u64, course: Option<u16>) {
+        self.last_time = Some(now);
+        if course.is_some() {
+            self.last_course = course;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> SmartBeaconConfig {
+        SmartBeaconConfig {
+            fast_speed_kmh: 90,
+            fast_rate_s: 180,
+            slow_speed_kmh: 5,
+            slow_rate_s: 1800,
+            turn_min_deg: 28,
+            turn_slope: 240,
+            turn_time_s: 15,
+        }
+    }
+
+    #[test]
+    fn rate_follows_speed() {
+        let cfg = cfg();
+        assert_eq!(rate_s(&cfg, 0), 1800);
+        assert_eq!(rate_s(&cfg, 5), 1800);
+        assert_eq!(rate_s(&cfg, 6), 1800);
+        assert_eq!(rate_s(&cfg, 45), 360);
+        assert_eq!(rate_s(&cfg, 90), 180);
+        assert_eq!(rate_s(&cfg, 130), 180);
+        assert_eq!(knots_to_kmh(48.6), 90);
+        assert_eq!(knots_to_kmh(-1.0), 0);
+    }
+
+    #[test]
+    fn beacons_at_the_rate_for_the_speed() {
+        let cfg = cfg();
+        let mut sb = SmartBeacon::new();
+        assert!(sb.due(0, &cfg, 0, None));
+        sb.sent(0, None);
+
+        assert!(!sb.due(359_999, &cfg, 45, Some(90)));
+        assert!(sb.due(360_000, &cfg, 45, Some(90)));
+        // Parked, only the slow rate
+        assert!(!sb.due(1_799_999, &cfg, 0, None));
+        assert!(sb.due(1_800_000, &cfg, 0, None));
+    }
+
+    #[test]
+    fn corners_are_pegged() {
+        let cfg = cfg();
+        let mut sb = SmartBeacon::new();
+        sb.sent(0, Some(350));
+
+        // 60 km/h: threshold 32 degrees, across north
+        assert_eq!(turn_threshold(&cfg, 60), 32);
+        assert!(!sb.due(20_000, &cfg, 60, Some(20)));
+        assert!(sb.due(20_000, &cfg, 60, Some(25)));
+        // Not before the minimum turn time
+        assert!(!sb.due(14_999, &cfg, 60, Some(80)));
+        // A wider threshold at low speed, none at all when crawling
+        assert!(!sb.due(20_000, &cfg, 10, Some(40)));
+        assert!(sb.due(20_000, &cfg, 10, Some(45)));
+        assert!(!sb.due(20_000, &cfg, 5, Some(170)));
+    }
+
+    #[test]
+    fn course_kept_while_stopped() {
+        let cfg = cfg();
+        let mut sb = SmartBeacon::new();
+        sb.sent(0, Some(90));
+        sb.sent(1_800_000, None);
+        assert!(sb.due(1_820_000, &cfg, 60, Some(180)));
+        assert!(!sb.due(1_820_000, &cfg, 60, Some(100)));
+    }
+}
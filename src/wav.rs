@@ -0,0 +1,183 @@
+//! WAV export of modulator output, for checking it on the host.
+//!
+//! Renders a whole transmission as mono 16-bit PCM, to play into Direwolf
+//! or another decoder, or to check the waveform in tests. Tests run on the
+//! host: `WAV_DIR=<dir> cargo test --target x86_64-unknown-linux-gnu wav`
+//! writes the reference transmissions out as well.
+
+use crate::hardware::audio;
+use crate::modem::Modulator;
+
+pub const HEADER_LEN: usize = 44;
+
+/// RIFF header for `samples` mono 16-bit samples at `sample_rate`.
+pub fn header(sample_rate: u32, samples: u32) -> [u8; HEADER_LEN] {
+    let data_len = samples * 2;
+    let mut h = [0u8; HEADER_LEN];
+    let mut put = |at: usize, bytes: &[u8]| h[at..at + bytes.len()].copy_from_slice(bytes);
+
+    put(0, b"RIFF");
+    put(4, &(36 + data_len).to_le_bytes());
+    put(8, b"WAVE");
+    put(12, b"fmt ");
+    put(16, &16u32.to_le_bytes());
+    put(20, &1u16.to_le_bytes()); // PCM
+    put(22, &1u16.to_le_bytes()); // mono
+    put(24, &sample_rate.to_le_bytes());
+    put(28, &(sample_rate * 2).to_le_bytes());
+    put(32, &2u16.to_le_bytes()); // block align
+    put(34, &16u16.to_le_bytes());
+    put(36, b"data");
+    put(40, &data_len.to_le_bytes());
+    h
+}
+
+/// Runs `modem` until it stops sending, handing every sample to `out` in
+/// the same buffer-sized steps the transmit task uses. The buffer the
+/// transmission ends in is sent whole. Returns the number of samples.
+pub fn render(modem: &mut impl Modulator, mut out: impl FnMut(i16)) -> u32 {
+    let mut buf = [0 as audio::Sample; audio::BUF_LEN];
+    let mut samples = 0;
+    loop {
+        let still_tx = modem.fill_samples(&mut buf);
+        for &s in buf.iter() {
+            out(s as i16);
+        }
+        samples += buf.len() as u32;
+
+        if !still_tx {
+            return samples;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::ax25::{self, AddressField, Framing};
+    use crate::config::ModemConfig;
+    use crate::demod::Demodulator;
+    use crate::modem::{sine, AfskModulator, ModemProfile};
+    use crate::onair::TxFrame;
+
+    /// `aprs.wav` as written by `afsk.py`: the frame below at 8 kHz, after
+    /// 72 zero bytes and three flags.
+    const AFSK_PY_WAV: &[u8] = include_bytes!("../testdata/afsk_py.wav");
+    const AFSK_PY_RATE: u32 = 8_000;
+
+    /// The frame `afsk.py` sends.
+    fn reference_frame() -> heapless::Vec<u8, { ax25::MAX_FRAME_LEN }> {
+        let dest = AddressField::from_text("APZ", 0).unwrap();
+        let src = AddressField::from_text("N0CALL", 7).unwrap();
+        let digis = [AddressField::from_text("WIDE1", 1).unwrap()];
+        let info = b"!4903.50N/07201.75WbPHG0020Test 001234";
+        ax25::build_ui_frame(dest, src, &digis, info).unwrap()
+    }
+
+    /// The whole transmission at `rate` as a WAV file.
+    fn transmission(rate: u32, profile: ModemProfile, framing: Framing, begin_flags: usize) -> Vec<u8> {
+        let mut modem = AfskModulator::new(rate);
+        modem.configure(&ModemConfig { profile, ..ModemConfig::default() });
+        let tx = TxFrame::new(framing, reference_frame()).unwrap();
+        modem.start(tx.into_bits(begin_flags, 3));
+
+        let mut pcm = Vec::new();
+        let samples = render(&mut modem, |s| pcm.extend_from_slice(&s.to_le_bytes()));
+
+        let mut wav = Vec::from(header(rate, samples));
+        wav.extend_from_slice(&pcm);
+        wav
+    }
+
+    fn samples(wav: &[u8]) -> Vec<i16> {
+        wav[HEADER_LEN..].chunks_exact(2).map(|s| i16::from_le_bytes([s[0], s[1]])).collect()
+    }
+
+    /// Whether `window` is nearer mark than space, by the power in each.
+    fn is_mark(window: &[i16], rate: u32) -> bool {
+        let power = |hz: u32| {
+            let step = ((hz as u64) << 32) / rate as u64;
+            let (mut i, mut q) = (0i64, 0i64);
+            for (n, &s) in window.iter().enumerate() {
+                let phase = (step * n as u64) as u32;
+                i += s as i64 * sine(phase) as i64;
+                q += s as i64 * sine(phase.wrapping_add(1 << 30)) as i64;
+            }
+            (i >> 15).pow(2) + (q >> 15).pow(2)
+        };
+        power(1200) > power(2200)
+    }
+
+    /// The data bits under NRZI, from the tone in the middle of each bit.
+    /// `start` gives the first sample of each bit.
+    fn nrzi_bits(samples: &[i16], rate: u32, bits: usize, start: impl Fn(usize) -> usize) -> Vec<bool> {
+        let tones: Vec<bool> = (0..bits)
+            .map(|k| is_mark(&samples[start(k) + 1..start(k) + 6], rate))
+            .collect();
+        tones.windows(2).map(|t| t[0] == t[1]).collect()
+    }
+
+    #[test]
+    fn header_describes_pcm() {
+        let h = header(8_000, 100);
+        assert_eq!(&h[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(h[4..8].try_into().unwrap()), 36 + 200);
+        assert_eq!(u32::from_le_bytes(h[24..28].try_into().unwrap()), 8_000);
+        assert_eq!(u32::from_le_bytes(h[28..32].try_into().unwrap()), 16_000);
+        assert_eq!(u32::from_le_bytes(h[40..44].try_into().unwrap()), 200);
+    }
+
+    /// Our waveform keys the same tones, bit for bit, as the reference
+    /// modulator in `afsk.py`, and our receiver takes either.
+    #[test]
+    fn matches_afsk_py() {
+        assert_eq!(&AFSK_PY_WAV[..HEADER_LEN], &header(AFSK_PY_RATE, (AFSK_PY_WAV.len() - HEADER_LEN) as u32 / 2));
+        let reference = samples(AFSK_PY_WAV);
+        // 75 fill bytes, the same length as afsk.py's, though all flags
+        let ours = samples(&transmission(AFSK_PY_RATE, ModemProfile::BELL_202, Framing::Ax25, 75));
+
+        for (name, wav) in [("afsk.py", &reference), ("ours", &ours)] {
+            let mut demod = Demodulator::new(AFSK_PY_RATE, ModemProfile::BELL_202);
+            let received = wav.iter().filter_map(|&s| demod.push_sample(s)).last();
+            assert_eq!(received, Some(reference_frame()), "{} decodes", name);
+        }
+
+        // afsk.py clocks bits with a 16-bit fraction a little under 1200/8000;
+        // ours moves to bit k on the sample that completes its period
+        let bits = (reference.len() * 9_830) >> 16;
+        let theirs = nrzi_bits(&reference, AFSK_PY_RATE, bits, |k| (k << 16).div_ceil(9_830));
+        let ours = nrzi_bits(&ours, AFSK_PY_RATE, bits, |k| ((k + 1) * 8_000).div_ceil(1_200) - 1);
+
+        // From the flags on; the fill before them differs
+        let flags = 72 * 8;
+        assert_eq!(theirs[flags..], ours[flags..]);
+    }
+
+    /// Every profile and framing decodes. Set `WAV_DIR` to write them out
+    /// for another decoder.
+    #[test]
+    fn transmissions_decode() {
+        let cases = [
+            (ModemProfile::BELL_202, Framing::Ax25, "bell202_ax25"),
+            (ModemProfile::BELL_202, Framing::Il2p, "bell202_il2p"),
+            (ModemProfile::HF_300, Framing::Ax25, "hf300_ax25"),
+        ];
+
+        let dir = std::env::var("WAV_DIR").ok();
+        for (profile, framing, name) in cases {
+            let rate = 9_600;
+            let wav = transmission(rate, profile, framing, 75);
+            if let Some(dir) = dir.as_ref() {
+                std::fs::write(std::format!("{}/{}.wav", dir, name), &wav).unwrap();
+            }
+
+            let mut demod = Demodulator::new(rate, profile);
+            let received = samples(&wav).into_iter().filter_map(|s| demod.push_sample(s)).last();
+            assert_eq!(received, Some(reference_frame()), "{} decodes", name);
+        }
+    }
+}